@@ -1,43 +1,59 @@
-import {useEffect, useState} from 'react'
-import reactLogo from './assets/react.svg'
-import viteLogo from '/vite.svg'
+import { useState } from 'react'
 import './App.css'
-import { main } from '../../wasm/pkg';
+import { diagnostics, parse } from '../../wasm/pkg';
+import { run } from './run';
+
+const EXAMPLE = `(defn square (x) (* x x))
+(io/println (square 4))
+(square 5)`;
 
 function App() {
-  const [input, setInput] = useState("")
-    const [data, setData] = useState("");
+  const [input, setInput] = useState(EXAMPLE)
+  const [output, setOutput] = useState("");
+  const [ast, setAst] = useState("");
+  const [errors, setErrors] = useState("");
+
+  const show = (value: unknown) => JSON.stringify(value, null, 4)
 
-    const run = () => {
-      setData(JSON.stringify(main(input), null, 4))
+  const play = async () => {
+    try {
+      setAst(show(parse(input)))
+    } catch (error) {
+      setAst(show(error))
+    }
+    setErrors(show(diagnostics(input)))
+    try {
+      const result = await run(input)
+      const value = result.value === null ? "" : `=> ${result.value}\n`
+      setOutput(result.output + value)
+    } catch (error) {
+      setOutput(error instanceof Error ? error.message : show(error))
     }
+  }
 
   return (
     <>
-      <div>
-        <a href="https://vitejs.dev" target="_blank">
-          <img src={viteLogo} className="logo" alt="Vite logo" />
-        </a>
-        <a href="https://react.dev" target="_blank">
-          <img src={reactLogo} className="logo react" alt="React logo" />
-        </a>
-      </div>
-      <h1>Vite + React</h1>
-      <div className="card">
-          <textarea value={input} onInput={(e) => setInput(e.currentTarget.value)} />
-        <button onClick={() => run()}>
-            Run
+      <h1>Wasp Playground</h1>
+      <div className="editor">
+        <textarea value={input} onInput={(e) => setInput(e.currentTarget.value)} />
+        <button onClick={() => play()}>
+          Run
         </button>
-          <pre>
-              {data}
-          </pre>
-        <p>
-          Edit <code>src/App.tsx</code> and save to test HMR
-        </p>
       </div>
-      <p className="read-the-docs">
-        Click on the Vite and React logos to learn more
-      </p>
+      <div className="panes">
+        <section>
+          <h2>Output</h2>
+          <pre>{output}</pre>
+        </section>
+        <section>
+          <h2>AST</h2>
+          <pre>{ast}</pre>
+        </section>
+        <section>
+          <h2>Diagnostics</h2>
+          <pre>{errors}</pre>
+        </section>
+      </div>
     </>
   )
 }
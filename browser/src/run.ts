@@ -0,0 +1,34 @@
+import { compile, type Io } from '../../wasm/pkg';
+
+export type RunResult = {
+  /** Everything the program printed via `io`. */
+  output: string;
+  /** The result of `main`, if the program has one. */
+  value: number | null;
+};
+
+/**
+ * Compiles the source, instantiates it with an `io` namespace, which
+ * captures what it prints, and calls its `main`.
+ *
+ * @throws {Diagnostic} if the source does not compile.
+ */
+export async function run(source: string): Promise<RunResult> {
+  const bytes = compile(source);
+  let output = '';
+  const io: Io = {
+    print: (value) => {
+      output += String(value);
+      return value;
+    },
+    println: (value) => {
+      output += `${value}\n`;
+      return value;
+    },
+  };
+
+  const { instance } = await WebAssembly.instantiate(bytes, { io });
+  const main = instance.exports.main;
+  const value = typeof main === 'function' ? (main() as number) : null;
+  return { output, value };
+}
@@ -1,7 +1,16 @@
-use clap::Parser;
-use std::path::PathBuf;
+mod manifest;
+mod runner;
+mod watch;
+
+use crate::manifest::Manifest;
+use clap::{Parser, ValueEnum};
+use compiler::compile::{OptLevel, Options};
+use std::path::{Path, PathBuf};
 use std::{fs, io};
 
+/// The module written by the compile command.
+const OUTPUT: &str = "program.wasm";
+
 #[derive(Debug, Parser)]
 pub struct Cli {
     #[clap(subcommand)]
@@ -14,20 +23,122 @@ enum Command {
     Run {
         /// File to be run, the default is main.
         file: Option<PathBuf>,
+
+        /// Run the file again, whenever it changes.
+        #[arg(long)]
+        watch: bool,
+
+        /// How to print errors in the program.
+        #[arg(long, value_enum, default_value_t = MessageFormat::Human)]
+        message_format: MessageFormat,
     },
 
     /// Compile the given file.
     Compile {
         /// File to be run, the default is main.
         file: Option<PathBuf>,
+
+        /// Compile the file again, whenever it or one of the namespaces
+        /// it requires changes.
+        #[arg(long)]
+        watch: bool,
+
+        /// Write the compiled module without validating it.
+        #[arg(long)]
+        no_validate: bool,
+
+        /// Evaluate the top-level expressions, when the module is
+        /// instantiated, instead of exporting them as main.
+        #[arg(long)]
+        start: bool,
+
+        /// Optimization level: 0 for none, 1 to fold constants, 2 to
+        /// inline small functions as well.
+        #[arg(short = 'O', default_value_t = 1, value_parser = clap::value_parser!(u8).range(0..=2))]
+        opt_level: u8,
+
+        /// Print the optimized intermediate representation of the
+        /// module in the given format.
+        #[arg(long, value_enum)]
+        dump_ir: Option<IrFormat>,
+
+        /// How to print errors in the program.
+        #[arg(long, value_enum, default_value_t = MessageFormat::Human)]
+        message_format: MessageFormat,
+    },
+
+    /// Build the project described by a wasp.toml.
+    Build {
+        /// Directory of the project, the default is the current one.
+        dir: Option<PathBuf>,
+
+        /// Write the compiled module without validating it.
+        #[arg(long)]
+        no_validate: bool,
+
+        /// Evaluate the top-level expressions, when the module is
+        /// instantiated, instead of exporting them as main.
+        #[arg(long)]
+        start: bool,
+
+        /// Optimization level: 0 for none, 1 to fold constants, 2 to
+        /// inline small functions as well.
+        #[arg(short = 'O', default_value_t = 1, value_parser = clap::value_parser!(u8).range(0..=2))]
+        opt_level: u8,
+
+        /// How to print errors in the program.
+        #[arg(long, value_enum, default_value_t = MessageFormat::Human)]
+        message_format: MessageFormat,
+    },
+
+    /// Run the tests of the project described by a wasp.toml.
+    Test {
+        /// Directory of the project, the default is the current one.
+        dir: Option<PathBuf>,
+
+        /// How to print errors in the program.
+        #[arg(long, value_enum, default_value_t = MessageFormat::Human)]
+        message_format: MessageFormat,
+    },
+
+    /// Create a new project.
+    New {
+        /// Name of the project and the directory to create it in.
+        name: String,
     },
+
+    /// Explain an error code, like W0011.
+    Explain {
+        /// The code of the error.
+        code: String,
+    },
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum IrFormat {
+    Text,
+    Json,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+enum MessageFormat {
+    /// Readable diagnostics on stderr.
+    Human,
+    /// One diagnostic per line as JSON on stdout.
+    Json,
 }
 
 #[derive(Debug)]
 pub enum Error {
     Io(io::Error),
     Json(serde_json::Error),
+    Toml(toml::de::Error),
     Parse(compiler::parse::error::Error),
+    Project(compiler::project::error::Error),
+    Wasm(wasmi::Error),
+    Notify(notify::Error),
+    /// The watcher stopped sending events.
+    WatchStopped,
 }
 
 impl From<io::Error> for Error {
@@ -42,38 +153,293 @@ impl From<serde_json::Error> for Error {
     }
 }
 
+impl From<toml::de::Error> for Error {
+    fn from(value: toml::de::Error) -> Self {
+        Error::Toml(value)
+    }
+}
+
 impl From<compiler::parse::error::Error> for Error {
     fn from(value: compiler::parse::error::Error) -> Self {
         Error::Parse(value)
     }
 }
 
+impl From<compiler::project::error::Error> for Error {
+    fn from(value: compiler::project::error::Error) -> Self {
+        Error::Project(value)
+    }
+}
+
+impl From<wasmi::Error> for Error {
+    fn from(value: wasmi::Error) -> Self {
+        Error::Wasm(value)
+    }
+}
+
+impl From<notify::Error> for Error {
+    fn from(value: notify::Error) -> Self {
+        Error::Notify(value)
+    }
+}
+
 fn main() -> Result<(), Error> {
     let cli = Cli::parse();
     match cli.command {
-        Command::Run { file } => {
+        Command::Run {
+            file,
+            watch,
+            message_format,
+        } => {
             let file = file.unwrap_or_else(|| "main.edn".into());
-            let result = fs::read_to_string(&file)?;
-            let filename = file.to_str().map(|x| x.to_string());
-            let result = compiler::parse(filename, result.as_str());
-            match result {
-                Ok(module) => {
-                    let module = serde_json::to_string(&module)?;
-                    println!("{}", module)
-                }
+            if watch {
+                watch::watch(|| {
+                    if let Err(err) = run(&file) {
+                        report(&err, message_format);
+                    }
+                    vec![file.clone()]
+                })?;
+            } else if let Err(err) = run(&file) {
+                report(&err, message_format);
+                std::process::exit(1);
+            }
+        }
+        Command::Compile {
+            file,
+            watch,
+            no_validate,
+            start,
+            opt_level,
+            dump_ir,
+            message_format,
+        } => {
+            let file = file.unwrap_or_else(|| "main.edn".into());
+            let options = Options {
+                validate: !no_validate,
+                start,
+                opt_level: optimization(opt_level),
+                source_map_url: Some(source_map(Path::new(OUTPUT))),
+                ..Options::default()
+            };
+            if watch {
+                let mut files = vec![file.clone()];
+                watch::watch(|| {
+                    match compile(&file, &options, dump_ir, Path::new(OUTPUT)) {
+                        Ok(()) => println!("Wrote {}", OUTPUT),
+                        Err(err) => report(&err, message_format),
+                    }
+                    // Keep watching the previous files, if a namespace
+                    // cannot be loaded, so fixing it triggers a recompile.
+                    if let Some(dependencies) = dependencies(&file) {
+                        files = dependencies;
+                    }
+                    files.clone()
+                })?;
+            } else if let Err(err) = compile(&file, &options, dump_ir, Path::new(OUTPUT)) {
+                report(&err, message_format);
+                std::process::exit(1);
+            }
+        }
+        Command::Build {
+            dir,
+            no_validate,
+            start,
+            opt_level,
+            message_format,
+        } => {
+            let dir = dir.unwrap_or_else(|| ".".into());
+            if let Err(err) = build(&dir, !no_validate, start, optimization(opt_level)) {
+                report(&err, message_format);
+                std::process::exit(1);
+            }
+        }
+        Command::Test {
+            dir,
+            message_format,
+        } => {
+            let dir = dir.unwrap_or_else(|| ".".into());
+            match runner::test(&dir) {
+                Ok(true) => {}
+                Ok(false) => std::process::exit(1),
                 Err(err) => {
-                    println!("{:?}", err);
+                    report(&err, message_format);
+                    std::process::exit(1);
                 }
             }
         }
-        Command::Compile { file } => {
-            let file = file.unwrap_or_else(|| "main.edn".into());
-            let result = fs::read_to_string(&file)?;
-            let filename = file.to_str().map(|x| x.to_string());
-            let result = compiler::compile(filename, result.as_str())?;
-            fs::write("program.wasm", result)?;
+        Command::New { name } => new(&name)?,
+        Command::Explain { code } => match compiler::reporting::code::lookup(&code) {
+            Some(code) => print!("{}", code.explanation),
+            None => {
+                eprintln!("Unknown error code {}", code);
+                std::process::exit(1);
+            }
+        },
+    }
+
+    Ok(())
+}
+
+fn run(file: &Path) -> Result<(), Error> {
+    let result = fs::read_to_string(file)?;
+    let filename = file.to_str().map(|x| x.to_string());
+    let module = compiler::parse(filename.clone(), result.as_str())
+        .map_err(|err| compiler::project::error::Error::Parse(filename, err))?;
+    println!("{}", serde_json::to_string(&module)?);
+    Ok(())
+}
+
+/// Compiles the file and writes the module to `output`.
+fn compile(
+    file: &Path,
+    options: &Options,
+    dump_ir: Option<IrFormat>,
+    output: &Path,
+) -> Result<(), Error> {
+    let result = compiler::compile_file(file, options)?;
+    match dump_ir {
+        Some(IrFormat::Text) => print!("{}", result.ir),
+        Some(IrFormat::Json) => println!("{}", serde_json::to_string_pretty(&result.ir)?),
+        None => {}
+    }
+    write(output, &result)
+}
+
+/// Prints the diagnostic of an error in the program in the given
+/// format, and any other error as is.
+fn report(err: &Error, format: MessageFormat) {
+    let diagnostic = match err {
+        Error::Parse(err) => err.to_diagnostic(None),
+        Error::Project(err) => err.to_diagnostic(),
+        err => {
+            eprintln!("{:?}", err);
+            return;
         }
+    };
+    match format {
+        MessageFormat::Human => eprintln!(
+            "{}\n  = run `wasp explain {}` for more information",
+            diagnostic, diagnostic.code
+        ),
+        MessageFormat::Json => println!(
+            "{}",
+            serde_json::to_string(&diagnostic).expect("diagnostics should serialize")
+        ),
+    }
+}
+
+/// Writes the compiled module to `output` and its source map next to
+/// it, see [`source_map`].
+fn write(output: &Path, result: &compiler::compile::Output) -> Result<(), Error> {
+    fs::write(output, &result.bytes)?;
+    fs::write(
+        output.with_file_name(source_map(output)),
+        serde_json::to_string(&result.source_map)?,
+    )?;
+    Ok(())
+}
+
+/// Returns the filename of the source map of the given module.
+fn source_map(output: &Path) -> String {
+    let filename = output.file_name().unwrap_or_default().to_string_lossy();
+    format!("{}.map", filename)
+}
+
+/// Returns the optimization level selected via `-O`.
+fn optimization(level: u8) -> OptLevel {
+    match level {
+        0 => OptLevel::O0,
+        1 => OptLevel::O1,
+        _ => OptLevel::O2,
     }
+}
 
+/// Returns the given file and the files of all namespaces it requires,
+/// if they can be loaded.
+fn dependencies(file: &Path) -> Option<Vec<PathBuf>> {
+    let namespaces = compiler::project::load_file(file).ok()?;
+    let mut files = vec![file.to_path_buf()];
+    files.extend(
+        namespaces
+            .iter()
+            .filter_map(|namespace| namespace.module.filename.as_ref().map(PathBuf::from)),
+    );
+    Some(files)
+}
+
+fn build(dir: &Path, validate: bool, start: bool, opt_level: OptLevel) -> Result<(), Error> {
+    let manifest = Manifest::read(dir)?;
+    let loader = manifest.loader(dir);
+    let output = manifest.output(dir);
+    let options = Options {
+        validate,
+        start,
+        opt_level,
+        source_map_url: Some(source_map(&output)),
+        ..manifest.options()
+    };
+    let result = compiler::compile_project(&manifest.project.entry, &loader, &options)?;
+    if let Some(parent) = output.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    write(&output, &result)?;
+    println!("Wrote {}", output.display());
     Ok(())
 }
+
+fn new(name: &str) -> Result<(), Error> {
+    let dir = PathBuf::from(name);
+    fs::create_dir(&dir)?;
+    fs::write(dir.join(manifest::FILENAME), manifest::template(name))?;
+
+    let source = compiler::project::FileLoader::path(&dir.join("src"), name);
+    if let Some(parent) = source.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(source, format!("(ns {})\n\n(defn main () 0)\n", name))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{compile, OUTPUT};
+    use compiler::compile::Options;
+    use std::fs;
+    use wasmi::{Caller, Engine, Linker, Module, Store};
+
+    #[test]
+    fn compile_script_using_io() {
+        let dir = std::env::temp_dir().join("wasp-compile-script-using-io");
+        fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("main.edn");
+        fs::write(&file, "(import io)\n(io/println (* 6 7))").unwrap();
+        let output = dir.join(OUTPUT);
+        compile(&file, &Options::default(), None, &output).unwrap();
+
+        let engine = Engine::default();
+        let module = Module::new(&engine, &fs::read(&output).unwrap()[..]).unwrap();
+        let mut store = Store::new(&engine, vec![]);
+        let mut linker = Linker::<Vec<f64>>::new(&engine);
+        for name in ["print", "println"] {
+            linker
+                .func_wrap("io", name, |mut caller: Caller<Vec<f64>>, x: f64| {
+                    caller.data_mut().push(x);
+                    x
+                })
+                .unwrap();
+        }
+        let instance = linker
+            .instantiate(&mut store, &module)
+            .unwrap()
+            .start(&mut store)
+            .unwrap();
+        instance
+            .get_typed_func::<(), f64>(&store, "main")
+            .unwrap()
+            .call(&mut store, ())
+            .unwrap();
+
+        assert_eq!(&vec![42.0], store.data());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,158 @@
+use compiler::compile::{Import, Options};
+use compiler::project::FileLoader;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// The name of the manifest file in the root directory of a project.
+pub const FILENAME: &str = "wasp.toml";
+
+/// The manifest of a project.
+///
+/// ```toml
+/// [project]
+/// name = "hello"
+/// entry = "hello.main"
+/// source-dirs = ["src"]
+/// output-dir = "target"
+/// exports = ["main"]
+///
+/// [imports.io]
+/// println = 1
+/// ```
+#[derive(Debug, Deserialize)]
+pub struct Manifest {
+    pub project: Project,
+    /// Functions provided by the host, by namespace and name, each
+    /// with its arity.
+    #[serde(default)]
+    pub imports: BTreeMap<String, BTreeMap<String, usize>>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Project {
+    pub name: String,
+    /// The namespace, whose functions are exported.
+    pub entry: String,
+    /// Directories namespaces are resolved in, relative to the project.
+    #[serde(default = "default_source_dirs")]
+    pub source_dirs: Vec<PathBuf>,
+    /// Directory the compiled module is written to, relative to the
+    /// project.
+    #[serde(default = "default_output_dir")]
+    pub output_dir: PathBuf,
+    /// Functions of the entry namespace to export. If missing, the ones
+    /// listed by its `(export ...)` forms or all public ones are.
+    pub exports: Option<Vec<String>>,
+}
+
+fn default_source_dirs() -> Vec<PathBuf> {
+    vec!["src".into()]
+}
+
+fn default_output_dir() -> PathBuf {
+    "target".into()
+}
+
+impl Manifest {
+    /// Reads the manifest of the project in the given directory.
+    pub fn read(dir: &Path) -> Result<Manifest, crate::Error> {
+        let content = fs::read_to_string(dir.join(FILENAME))?;
+        Ok(toml::from_str(&content)?)
+    }
+
+    /// Returns a loader for the source directories of the project in
+    /// the given directory.
+    pub fn loader(&self, dir: &Path) -> FileLoader {
+        let roots = self
+            .project
+            .source_dirs
+            .iter()
+            .map(|source_dir| dir.join(source_dir))
+            .collect();
+        FileLoader::new(roots)
+    }
+
+    pub fn options(&self) -> Options {
+        let imports = self
+            .imports
+            .iter()
+            .flat_map(|(module, functions)| {
+                functions.iter().map(|(name, arity)| Import {
+                    module: module.clone(),
+                    name: name.clone(),
+                    arity: *arity,
+                })
+            })
+            .collect();
+
+        Options {
+            exports: self.project.exports.clone(),
+            imports,
+            ..Options::default()
+        }
+    }
+
+    /// Returns the path of the compiled module of the project in the
+    /// given directory.
+    pub fn output(&self, dir: &Path) -> PathBuf {
+        dir.join(&self.project.output_dir)
+            .join(format!("{}.wasm", self.project.name))
+    }
+}
+
+/// Returns the manifest of a new project with the given name.
+pub fn template(name: &str) -> String {
+    format!(
+        r#"[project]
+name = "{name}"
+entry = "{name}"
+source-dirs = ["src"]
+output-dir = "target"
+
+[imports]
+"#
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::manifest::{template, Manifest};
+    use std::path::{Path, PathBuf};
+
+    #[test]
+    fn parse_manifest() {
+        let manifest: Manifest = toml::from_str(
+            r#"
+            [project]
+            name = "hello"
+            entry = "hello.main"
+            exports = ["main"]
+
+            [imports.io]
+            println = 1
+            "#,
+        )
+        .unwrap();
+        let options = manifest.options();
+
+        assert_eq!(vec![PathBuf::from("src")], manifest.project.source_dirs);
+        assert_eq!(
+            PathBuf::from("app/target/hello.wasm"),
+            manifest.output(Path::new("app"))
+        );
+        assert_eq!(Some(vec!["main".to_string()]), options.exports);
+        assert_eq!("println", options.imports[0].name);
+        assert_eq!(1, options.imports[0].arity);
+    }
+
+    #[test]
+    fn parse_template() {
+        let manifest: Manifest = toml::from_str(&template("hello")).unwrap();
+
+        assert_eq!("hello", manifest.project.entry);
+        assert!(manifest.imports.is_empty());
+    }
+}
@@ -0,0 +1,85 @@
+use crate::manifest::Manifest;
+use crate::Error;
+use compiler::compile::{Import, Test};
+use std::path::Path;
+use wasmi::{core::ValType, Engine, Func, FuncType, Linker, Module, Store, Val};
+
+/// Compiles the tests of all namespaces of the project in the given
+/// directory and runs each of them in a fresh instance.
+///
+/// Returns whether all tests passed.
+pub fn test(dir: &Path) -> Result<bool, Error> {
+    let manifest = Manifest::read(dir)?;
+    let loader = manifest.loader(dir);
+    let namespaces = loader.namespaces();
+    let namespaces: Vec<&str> = namespaces.iter().map(|ns| ns.as_str()).collect();
+    let options = manifest.options();
+    let output = compiler::compile::compile_tests(&namespaces, &loader, &options)?;
+
+    let engine = Engine::default();
+    let module = Module::new(&engine, &output.bytes[..])?;
+    let mut failed = 0;
+    for test in &output.tests {
+        match run(&engine, &module, &output.imports, test) {
+            Ok(()) => println!("PASS {}", test.export),
+            Err(reason) => {
+                failed += 1;
+                println!("FAIL {}: {}", test.export, reason);
+            }
+        }
+    }
+
+    println!(
+        "\n{} passed, {} failed",
+        output.tests.len() - failed,
+        failed
+    );
+    Ok(failed == 0)
+}
+
+/// Runs a single test, returning why it failed, if it did.
+fn run(engine: &Engine, module: &Module, imports: &[Import], test: &Test) -> Result<(), String> {
+    let mut store = Store::new(engine, ());
+    let mut linker = Linker::<()>::new(engine);
+    for import in imports {
+        let ty = FuncType::new(vec![ValType::F64; import.arity], vec![ValType::F64]);
+        let name = format!("{}/{}", import.module, import.name);
+        let func = Func::new(&mut store, ty, move |_, params, results| {
+            let args: Vec<String> = params
+                .iter()
+                .map(|param| match param {
+                    Val::F64(value) => f64::from(*value).to_string(),
+                    param => format!("{:?}", param),
+                })
+                .collect();
+            println!("  {} {}", name, args.join(" "));
+            results[0] = Val::F64(0.0.into());
+            Ok(())
+        });
+        linker
+            .define(&import.module, &import.name, func)
+            .map_err(|err| err.to_string())?;
+    }
+
+    let instance = linker
+        .instantiate(&mut store, module)
+        .and_then(|instance| instance.start(&mut store))
+        .map_err(|err| err.to_string())?;
+    let result = instance
+        .get_typed_func::<(), f64>(&store, &test.export)
+        .and_then(|func| func.call(&mut store, ()))
+        .map_err(|err| err.to_string())?;
+
+    if result == 0.0 {
+        return Ok(());
+    }
+
+    let filename = test.filename.as_deref().unwrap_or("<unknown>");
+    match test.assertions.get(result as usize - 1) {
+        Some(region) => Err(format!(
+            "assertion failed at {}:{}:{}",
+            filename, region.start.line, region.start.col
+        )),
+        None => Err(format!("failed in {}", filename)),
+    }
+}
@@ -0,0 +1,66 @@
+use crate::Error;
+use notify::{Event, RecursiveMode, Watcher};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::time::Duration;
+
+/// How long to wait for further changes, before acting on a change, so
+/// saving several files at once only triggers `action` once.
+const DEBOUNCE: Duration = Duration::from_millis(100);
+
+/// Calls `action` and calls it again, whenever one of the files it
+/// returns changes, until interrupted.
+///
+/// The directories of the files are watched rather than the files
+/// themselves, since many editors replace a file on save.
+pub fn watch<F: FnMut() -> Vec<PathBuf>>(mut action: F) -> Result<(), Error> {
+    let (sender, receiver) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(sender)?;
+    let mut dirs: HashSet<PathBuf> = HashSet::new();
+
+    loop {
+        let files: HashSet<PathBuf> = action().iter().filter_map(|file| canonical(file)).collect();
+
+        let watched: HashSet<PathBuf> = files
+            .iter()
+            .filter_map(|file| file.parent().map(Path::to_path_buf))
+            .collect();
+        for dir in dirs.difference(&watched) {
+            watcher.unwatch(dir)?;
+        }
+        for dir in watched.difference(&dirs) {
+            watcher.watch(dir, RecursiveMode::NonRecursive)?;
+        }
+        dirs = watched;
+
+        wait_for_change(&receiver, &files)?;
+        while receiver.recv_timeout(DEBOUNCE).is_ok() {}
+    }
+}
+
+/// Blocks until an event concerning one of the given files is received.
+fn wait_for_change(
+    receiver: &mpsc::Receiver<notify::Result<Event>>,
+    files: &HashSet<PathBuf>,
+) -> Result<(), Error> {
+    loop {
+        let event = receiver.recv().map_err(|_| Error::WatchStopped)??;
+        if event.kind.is_access() {
+            continue;
+        }
+        if event
+            .paths
+            .iter()
+            .any(|path| canonical(path).is_some_and(|path| files.contains(&path)))
+        {
+            return Ok(());
+        }
+    }
+}
+
+fn canonical(path: &Path) -> Option<PathBuf> {
+    path.canonicalize()
+        .or_else(|_| std::path::absolute(path))
+        .ok()
+}
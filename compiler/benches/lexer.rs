@@ -0,0 +1,26 @@
+use compiler::parse::lexer::lexer;
+use compiler::parse::parse;
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+
+/// Returns a module of roughly `size` bytes, consisting of copies of a
+/// few typical definitions.
+fn module(size: usize) -> String {
+    let defns = "(ns my.app (:require [my.util :as u]))\n\
+                 (defn fact (n)\n  (if (<= n 1) 1 (* n (fact (- n 1)))))\n\
+                 (defn sum (xs) (if (empty? xs) 0 (+ (first xs) (sum (rest xs)))))\n\
+                 (defn main () (u/println (sum (list 1.5 2.25 3 42 (fact 10)))))\n";
+    defns.repeat(size / defns.len() + 1)
+}
+
+fn lex(c: &mut Criterion) {
+    let input = module(4 * 1024 * 1024);
+    let mut group = c.benchmark_group("lexer");
+    group.throughput(Throughput::Bytes(input.len() as u64));
+    group.sample_size(10);
+    group.bench_function("lex 4 MiB", |b| b.iter(|| lexer(&input).count()));
+    group.bench_function("parse 4 MiB", |b| b.iter(|| parse(None, &input).unwrap()));
+    group.finish();
+}
+
+criterion_group!(benches, lex);
+criterion_main!(benches);
@@ -1,138 +1,2254 @@
+mod closure;
+pub mod ir;
+mod lower;
+mod optimize;
+mod reachable;
+mod resolve;
+mod runtime;
+mod source_map;
+mod validate;
+
+use crate::compile::optimize::Optimizer;
+use crate::compile::runtime::Builtin;
 use crate::parse;
-use crate::parse::{Expr, Module};
+use crate::parse::Expr;
+use crate::project;
+use crate::project::error::Error;
+use crate::project::{Loader, Namespace};
+use crate::reporting::Region;
+pub use optimize::OptLevel;
+pub use source_map::SourceMap;
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
 use wasm_encoder::{
-    CodeSection, ExportKind, ExportSection, Function, FunctionSection, Instruction, TypeSection,
-    ValType,
+    BlockType, CodeSection, ConstExpr, CustomSection, ElementSection, Elements, EntityType,
+    ExportKind, ExportSection, Function, FunctionSection, GlobalSection, GlobalType, ImportSection,
+    IndirectNameMap, Instruction, MemArg, MemorySection, MemoryType, NameMap, NameSection, RefType,
+    StartSection, TableSection, TableType, TypeSection, ValType,
 };
 
-pub fn compile(filename: Option<String>, input: &str) -> Result<Vec<u8>, parse::error::Error> {
-    let module = parse::parse(filename, input)?;
-    Ok(codegen(module))
+/// Source of the `core` namespace, which is compiled with every module.
+///
+/// It wraps every special form, which evaluates all of its arguments, in
+/// a function of the same name to make it usable as a value, i.e.
+/// `(map inc xs)`. Calls always refer to the special form, so the wrappers
+/// do not recurse, and no other namespace may redefine a special form.
+const CORE: &str = include_str!("compile/core.edn");
+
+const CORE_NAMESPACE: &str = "core";
+
+/// The name the top-level expressions of the entry namespace are
+/// exported as.
+const MAIN: &str = "main";
+
+/// The metadata marking a function as private.
+const PRIVATE: &str = "^:private";
+
+/// Options for compiling a module.
+#[derive(Debug)]
+pub struct Options {
+    /// Names of the functions of the entry namespace to export. If
+    /// there are none, the names listed by its `(export ...)` forms
+    /// are exported or, without any, all public functions.
+    pub exports: Option<Vec<String>>,
+    /// Functions provided by the host.
+    pub imports: Vec<Import>,
+    /// Whether to compile the `deftest`s of all namespaces.
+    pub tests: bool,
+    /// Whether to validate the compiled module, which is only worth
+    /// skipping to inspect an invalid module.
+    pub validate: bool,
+    /// URL of the source map, which is embedded into the module for
+    /// debuggers to find it, see [`Output::source_map`].
+    pub source_map_url: Option<String>,
+    /// Whether to evaluate the top-level expressions, when the module
+    /// is instantiated, instead of exporting them as `main`.
+    pub start: bool,
+    pub opt_level: OptLevel,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            exports: None,
+            imports: vec![],
+            tests: false,
+            validate: true,
+            source_map_url: None,
+            start: false,
+            opt_level: OptLevel::default(),
+        }
+    }
+}
+
+/// Functions of the modules every host is expected to provide by their
+/// name and arity, which `(import module)` declares, unless
+/// [`Options::imports`] declares functions of the module already.
+pub const HOST_MODULES: [(&str, &[(&str, usize)]); 1] = [("io", &[("print", 1), ("println", 1)])];
+
+/// Returns the functions of the given module of [`HOST_MODULES`].
+pub fn host_module(module: &str) -> Option<Vec<Import>> {
+    let (_, functions) = HOST_MODULES.iter().find(|(name, _)| *name == module)?;
+    let imports = functions
+        .iter()
+        .map(|(name, arity)| Import {
+            module: module.to_string(),
+            name: name.to_string(),
+            arity: *arity,
+        })
+        .collect();
+    Some(imports)
+}
+
+/// A function provided by the host, which is called as `module/name`.
+#[derive(Debug, Clone)]
+pub struct Import {
+    pub module: String,
+    pub name: String,
+    pub arity: usize,
+}
+
+/// The result of compiling a module.
+#[derive(Debug)]
+pub struct Output {
+    pub bytes: Vec<u8>,
+    /// The tests, if they have been compiled, see [`Options::tests`].
+    pub tests: Vec<Test>,
+    /// Maps offsets of instructions in `bytes` to the source code they
+    /// have been compiled from.
+    pub source_map: SourceMap,
+    /// The optimized intermediate representation of the module.
+    pub ir: ir::Program,
+    /// The functions the module imports, see [`HOST_MODULES`].
+    pub imports: Vec<Import>,
+}
+
+/// A test defined via `(deftest name body...)`.
+///
+/// The test is run by calling the function exported as `export`, which
+/// returns `0`, if all of its assertions passed, or the position of the
+/// first failing assertion in `assertions` plus one.
+#[derive(Debug)]
+pub struct Test {
+    pub namespace: String,
+    pub name: String,
+    pub export: String,
+    pub filename: Option<String>,
+    pub assertions: Vec<Region>,
+}
+
+/// Compiles a single module, which may not require other namespaces.
+pub fn compile(filename: Option<String>, input: &str) -> Result<Vec<u8>, Error> {
+    Ok(compile_source(filename, input, &Options::default())?.bytes)
+}
+
+/// Compiles a single module with the given options, which may not
+/// require other namespaces.
+pub fn compile_source(
+    filename: Option<String>,
+    input: &str,
+    options: &Options,
+) -> Result<Output, Error> {
+    let module =
+        parse::parse(filename.clone(), input).map_err(|err| Error::Parse(filename, err))?;
+    let namespaces = project::load(Namespace::new(module)?, &HashMap::new())?;
+    codegen(&namespaces, options)
+}
+
+/// Compiles the file at the given path together with all namespaces
+/// it requires into one module.
+pub fn compile_file(path: &Path, options: &Options) -> Result<Output, Error> {
+    let namespaces = project::load_file(path)?;
+    codegen(&namespaces, options)
+}
+
+/// Compiles the namespace `entry` found by `loader` together with all
+/// namespaces it requires into one module.
+pub fn compile_project<L: Loader>(
+    entry: &str,
+    loader: &L,
+    options: &Options,
+) -> Result<Output, Error> {
+    let namespaces = project::load_entries(&[entry], loader)?;
+    codegen(&namespaces, options)
+}
+
+/// Compiles the tests of the given namespaces and all namespaces they
+/// require into one module.
+pub fn compile_tests<L: Loader>(
+    namespaces: &[&str],
+    loader: &L,
+    options: &Options,
+) -> Result<Output, Error> {
+    let namespaces = project::load_entries(namespaces, loader)?;
+    let options = Options {
+        exports: Some(vec![]),
+        imports: options.imports.clone(),
+        tests: true,
+        validate: options.validate,
+        opt_level: options.opt_level,
+        ..Options::default()
+    };
+    codegen(&namespaces, &options)
 }
 
 struct WasmModule {
-    types: TypeSection,
+    types: Types,
+    imports: ImportSection,
     functions: FunctionSection,
+    tables: TableSection,
+    memories: MemorySection,
+    globals: GlobalSection,
     exports: ExportSection,
+    elements: ElementSection,
     code: CodeSection,
-    type_idx: u32,
+    /// All functions by their qualified name, see [`qualified`].
+    defns: HashMap<String, Defn>,
+    /// Index of the first builtin, which come right after the imports.
+    builtins: u32,
+    closures: Closures,
+    /// Function indices of the tests by their qualified name, if they
+    /// are compiled.
+    test_indices: HashMap<String, u32>,
+    tests: Vec<Test>,
+    /// Function indices of the functions evaluating the top-level
+    /// expressions of each namespace, in the order of the namespaces.
+    scripts: Vec<(String, u32)>,
+    start: Option<u32>,
+    /// Where each function comes from, by function index.
+    origins: Vec<Origin>,
+}
+
+/// Where a function of the compiled module comes from.
+#[derive(Debug, Clone)]
+struct Origin {
+    name: String,
+    filename: Option<String>,
+    /// The region of its definition, if it is defined in source code.
+    region: Option<Region>,
+    /// Names of its parameters and locals by their index.
+    locals: Vec<(u32, String)>,
+    /// Offsets into its body, where the instructions of an expression
+    /// start, with the region of the expression.
+    offsets: Vec<(usize, Region)>,
+}
+
+impl Origin {
+    /// Returns the origin of a function, which is not defined in source
+    /// code.
+    fn generated(name: String) -> Self {
+        Origin {
+            name,
+            filename: None,
+            region: None,
+            locals: vec![],
+            offsets: vec![],
+        }
+    }
+}
+
+/// A function defined via `defn` or imported from the host.
+pub(crate) struct Defn {
+    idx: u32,
+    arity: usize,
+}
+
+/// Functions, which can be called indirectly via a closure.
+///
+/// Each of them takes the closure itself as its first parameter,
+/// followed by the actual arguments. They are added to the module
+/// after all `defn`s, starting at function index `offset`, and their
+/// position in `functions` is their index in the table. Wrappers have
+/// no origin of their own, see [`Closures::wrapper`].
+///
+/// A closure is a pointer to memory, where the first 8 bytes hold the
+/// table index of its function, followed by the values of the
+/// captured variables, 8 bytes each.
+struct Closures {
+    offset: u32,
+    functions: Vec<(usize, Function, Option<Origin>)>,
+    /// Table indices of closures wrapping named functions, by their
+    /// function index.
+    wrappers: HashMap<u32, u32>,
+}
+
+impl Closures {
+    /// Adds the given function with the given arity, returning its
+    /// table index.
+    fn add(&mut self, arity: usize, func: Function, origin: Option<Origin>) -> u32 {
+        self.functions.push((arity, func, origin));
+        self.functions.len() as u32 - 1
+    }
+
+    /// Returns the table index of a closure, which calls the function
+    /// at index `idx` with the given arity.
+    fn wrapper(&mut self, idx: u32, arity: usize) -> u32 {
+        if let Some(table_idx) = self.wrappers.get(&idx) {
+            return *table_idx;
+        }
+
+        let mut func = Function::new(vec![]);
+        for param in 1..=arity {
+            func.instruction(&Instruction::LocalGet(param as u32));
+        }
+        func.instruction(&Instruction::Call(idx));
+        func.instruction(&Instruction::End);
+        let table_idx = self.add(arity, func, None);
+        self.wrappers.insert(idx, table_idx);
+        table_idx
+    }
+}
+
+/// The type section, where each signature is only added once.
+struct Types {
+    section: TypeSection,
+    signatures: HashMap<(Vec<ValType>, Vec<ValType>), u32>,
+}
+
+impl Types {
+    /// Returns the index of the function type with the given
+    /// signature, adding it to the type section if necessary.
+    fn signature(&mut self, params: Vec<ValType>, results: Vec<ValType>) -> u32 {
+        let next_idx = self.signatures.len() as u32;
+        let section = &mut self.section;
+        *self
+            .signatures
+            .entry((params, results))
+            .or_insert_with_key(|(params, results)| {
+                section.function(params.clone(), results.clone());
+                next_idx
+            })
+    }
+
+    /// Returns the index of the type of closure functions with the
+    /// given arity.
+    fn closure(&mut self, arity: usize) -> u32 {
+        self.signature(vec![ValType::F64; arity + 1], vec![ValType::F64])
+    }
 }
 
 impl WasmModule {
-    fn get_and_increment_type_idx(&mut self) -> u32 {
-        let cur = self.type_idx;
-        self.type_idx += 1;
-        cur
+    fn add_function(
+        &mut self,
+        params: Vec<ValType>,
+        results: Vec<ValType>,
+        func: &Function,
+        origin: Origin,
+    ) {
+        let type_idx = self.types.signature(params, results);
+        self.functions.function(type_idx);
+        self.code.function(func);
+        self.origins.push(origin);
     }
 }
 
-fn codegen(module: Module) -> Vec<u8> {
+/// Compiles the given namespaces in dependency order into one module.
+///
+/// Only the functions of the last namespace are exported.
+fn codegen(namespaces: &[Namespace], options: &Options) -> Result<Output, Error> {
+    let core = parse::parse(Some("core.edn".to_string()), CORE).expect("core should parse");
+    let core = Namespace {
+        name: CORE_NAMESPACE.to_string(),
+        requires: vec![],
+        module: core,
+    };
+    let modules: Vec<(&Namespace, bool)> = std::iter::once((&core, false))
+        .chain(
+            namespaces
+                .iter()
+                .enumerate()
+                .map(|(i, namespace)| (namespace, i + 1 == namespaces.len())),
+        )
+        .collect();
+
+    let imports = imports(namespaces, options);
     let mut wasm_module = WasmModule {
-        types: TypeSection::new(),
+        types: Types {
+            section: TypeSection::new(),
+            signatures: HashMap::new(),
+        },
+        imports: ImportSection::new(),
         functions: FunctionSection::new(),
+        tables: TableSection::new(),
+        memories: MemorySection::new(),
+        globals: GlobalSection::new(),
         exports: ExportSection::new(),
+        elements: ElementSection::new(),
         code: CodeSection::new(),
-        type_idx: 0,
+        defns: HashMap::new(),
+        builtins: imports.len() as u32,
+        closures: Closures {
+            offset: 0,
+            functions: vec![],
+            wrappers: HashMap::new(),
+        },
+        test_indices: HashMap::new(),
+        tests: vec![],
+        scripts: vec![],
+        start: None,
+        origins: vec![],
+    };
+
+    for (idx, import) in imports.iter().enumerate() {
+        let type_idx = wasm_module
+            .types
+            .signature(vec![ValType::F64; import.arity], vec![ValType::F64]);
+        wasm_module
+            .imports
+            .import(&import.module, &import.name, EntityType::Function(type_idx));
+        let defn = Defn {
+            idx: idx as u32,
+            arity: import.arity,
+        };
+        let name = qualified(&import.module, &import.name);
+        wasm_module.origins.push(Origin::generated(name.clone()));
+        wasm_module.defns.insert(name, defn);
+    }
+
+    let arity = modules
+        .iter()
+        .flat_map(|(namespace, _)| &namespace.module.expressions)
+        .map(max_arity)
+        .chain(imports.iter().map(|import| import.arity))
+        .chain(Builtin::ALL.map(|builtin| builtin.params().len()))
+        .max()
+        .unwrap_or_default();
+    compile_runtime(&mut wasm_module, arity);
+
+    let has_script = modules
+        .iter()
+        .any(|(namespace, _)| namespace.module.expressions.iter().any(is_top_level));
+    let (exports, exports_main) = match namespaces.last() {
+        Some(entry) => exports(entry, options, has_script)?,
+        None => (HashSet::new(), false),
+    };
+
+    let mut roots = vec![];
+    for (namespace, _) in &modules {
+        for expr in &namespace.module.expressions {
+            if is_top_level(expr) {
+                roots.push((*namespace, expr));
+            } else if let Some((_, body)) = as_deftest(expr).filter(|_| options.tests) {
+                roots.extend(body.iter().map(|expr| (*namespace, expr)));
+            }
+        }
+    }
+    let functions = match namespaces.last() {
+        Some(entry) => exports
+            .iter()
+            .map(|name| qualified(&entry.name, name))
+            .collect(),
+        None => vec![],
     };
+    let all: Vec<&Namespace> = modules.iter().map(|(namespace, _)| *namespace).collect();
+    resolve::resolve(&all, &imports)?;
+    let reachable = reachable::reachable(&all, functions, roots);
 
-    for expr in &module.expressions {
-        compile_expr(expr, &mut wasm_module);
+    // Functions may call each other regardless of the order they have
+    // been defined in, so we need to know all of them upfront.
+    let mut next_idx = wasm_module.builtins + Builtin::ALL.len() as u32;
+    for (namespace, is_entry) in &modules {
+        for expr in &namespace.module.expressions {
+            let reachable = |name: &str| reachable.contains(&qualified(&namespace.name, name));
+            if let Some((name, params, _)) = as_defn(expr).filter(|(name, _, _)| reachable(name)) {
+                let defn = Defn {
+                    idx: next_idx,
+                    arity: params.len(),
+                };
+                wasm_module
+                    .defns
+                    .insert(qualified(&namespace.name, name), defn);
+                next_idx += 1;
+            } else if let Some((name, _)) = as_deftest(expr).filter(|_| options.tests) {
+                wasm_module
+                    .test_indices
+                    .insert(qualified(&namespace.name, name), next_idx);
+                next_idx += 1;
+            }
+        }
+
+        // The entry namespace evaluates the top-level expressions of
+        // all namespaces it requires, even if it has none of its own.
+        let is_script = namespace.module.expressions.iter().any(is_top_level);
+        if is_script || (*is_entry && !wasm_module.scripts.is_empty()) {
+            wasm_module.scripts.push((namespace.name.clone(), next_idx));
+            next_idx += 1;
+        }
+    }
+    wasm_module.closures.offset = next_idx;
+
+    let mut bodies = vec![];
+    for (namespace, is_entry) in modules {
+        let aliases = namespace.aliases();
+        for expr in &namespace.module.expressions {
+            let exported = |name: &str| is_entry && exports.contains(name);
+            bodies.extend(lower_expr(
+                expr,
+                namespace,
+                &aliases,
+                &exported,
+                &mut wasm_module,
+            ));
+        }
+        bodies.extend(lower_script(
+            &mut wasm_module,
+            namespace,
+            &aliases,
+            is_entry,
+            options.start,
+        ));
     }
 
+    let defns = bodies.iter().filter(|body| body.is_defn);
+    let optimizer = Optimizer::new(
+        options.opt_level,
+        defns.map(|body| (body.idx, &body.function)),
+    );
+    let mut functions = HashMap::new();
+    for mut body in bodies {
+        optimizer.optimize(&mut body.function);
+        compile_body(&mut wasm_module, &mut body);
+        functions.insert(body.idx, body.function);
+    }
+    let ir = ir::Program {
+        functions: (0..wasm_module.closures.offset)
+            .map(|idx| ir::Definition {
+                index: idx,
+                name: wasm_module.origins[idx as usize].name.clone(),
+                function: functions.remove(&idx),
+            })
+            .collect(),
+    };
+
+    if exports_main {
+        if let Some((_, idx)) = wasm_module.scripts.last() {
+            wasm_module.exports.export(MAIN, ExportKind::Func, *idx);
+        }
+    }
+
+    compile_closures(&mut wasm_module);
+
     let mut module = wasm_encoder::Module::new();
 
     module
-        .section(&wasm_module.types)
+        .section(&wasm_module.types.section)
+        .section(&wasm_module.imports)
         .section(&wasm_module.functions)
-        .section(&wasm_module.exports)
-        .section(&wasm_module.code);
+        .section(&wasm_module.tables)
+        .section(&wasm_module.memories)
+        .section(&wasm_module.globals)
+        .section(&wasm_module.exports);
+    if let Some(function_index) = wasm_module.start {
+        module.section(&StartSection { function_index });
+    }
+    module
+        .section(&wasm_module.elements)
+        .section(&wasm_module.code)
+        .section(&names(&wasm_module.origins));
+
+    if let Some(url) = &options.source_map_url {
+        let mut data = vec![];
+        wasm_encoder::Encode::encode(url.as_str(), &mut data);
+        module.section(&CustomSection {
+            name: Cow::Borrowed("sourceMappingURL"),
+            data: Cow::Owned(data),
+        });
+    }
+
+    let bytes = module.finish();
+    if options.validate {
+        validate::validate(&bytes, &wasm_module.origins)?;
+    }
 
-    module.finish()
+    let source_map = SourceMap::new(&bytes, &wasm_module.origins);
+    Ok(Output {
+        bytes,
+        tests: wasm_module.tests,
+        source_map,
+        ir,
+        imports,
+    })
+}
+
+/// Adds the memory, the heap pointer and the builtins, where closures
+/// take at most `max_arity` arguments, see [`Builtin::Apply`].
+fn compile_runtime(wasm_module: &mut WasmModule, max_arity: usize) {
+    wasm_module.memories.memory(MemoryType {
+        minimum: 1,
+        maximum: None,
+        memory64: false,
+        shared: false,
+    });
+    wasm_module.exports.export("memory", ExportKind::Memory, 0);
+    wasm_module.globals.global(
+        GlobalType {
+            val_type: ValType::I32,
+            mutable: true,
+        },
+        &ConstExpr::i32_const(runtime::HEAP_START),
+    );
+
+    let offset = wasm_module.builtins;
+    let closures: Vec<u32> = (0..=max_arity)
+        .map(|arity| wasm_module.types.closure(arity))
+        .collect();
+    for builtin in Builtin::ALL {
+        let func = builtin.function(offset, &closures);
+        let origin = Origin::generated(qualified("runtime", builtin.name()));
+        wasm_module.add_function(builtin.params(), builtin.results(), &func, origin);
+    }
+}
+
+/// Returns the highest number of parameters of a `defn` or `fn` in the
+/// given expression.
+fn max_arity(expr: &Expr) -> usize {
+    let (own, exprs) = match expr {
+        Expr::List { expressions, .. } => {
+            let own = match expressions.as_slice() {
+                [Expr::Symbol { value, .. }, Expr::List {
+                    expressions: params,
+                    ..
+                }, _]
+                    if value == "fn" =>
+                {
+                    params.len()
+                }
+                _ => as_defn(expr).map_or(0, |(_, params, _)| params.len()),
+            };
+            (own, expressions)
+        }
+        Expr::Vector { expressions, .. } => (0, expressions),
+        _ => return 0,
+    };
+    exprs.iter().map(max_arity).fold(own, usize::max)
 }
 
-fn compile_expr(expr: &Expr, wasm_module: &mut WasmModule) {
+/// Returns the names of the functions of the entry namespace to
+/// export and whether to export its top-level expressions as `main`,
+/// see [`Options::exports`].
+fn exports(
+    entry: &Namespace,
+    options: &Options,
+    has_script: bool,
+) -> Result<(HashSet<String>, bool), Error> {
+    let defns: Vec<(&str, &Expr)> = entry
+        .module
+        .expressions
+        .iter()
+        .filter_map(|expr| as_defn(expr).map(|(name, _, _)| (name, expr)))
+        .collect();
+    let defn = |name: &str| defns.iter().find(|(defn, _)| *defn == name);
+    let has_main = has_script && !options.start;
+    let script = entry
+        .module
+        .expressions
+        .iter()
+        .find(|expr| is_top_level(expr));
+    let duplicate_main = |expr: &Expr| {
+        Error::DuplicateMain(
+            entry.module.filename.clone(),
+            *expr.region(),
+            script.map(|script| *script.region()),
+        )
+    };
+
+    let listed: Vec<String> = entry
+        .module
+        .expressions
+        .iter()
+        .filter_map(as_export)
+        .flatten()
+        .map(String::from)
+        .collect();
+    let explicit = options
+        .exports
+        .clone()
+        .or_else(|| (!listed.is_empty()).then_some(listed));
+
+    let Some(explicit) = explicit else {
+        let exports: HashSet<String> = defns
+            .iter()
+            .filter(|(_, expr)| !is_private(expr))
+            .map(|(name, _)| name.to_string())
+            .collect();
+        if let Some((_, expr)) = defn(MAIN).filter(|_| has_main && exports.contains(MAIN)) {
+            return Err(duplicate_main(expr));
+        }
+        return Ok((exports, has_main));
+    };
+
+    let mut exports = HashSet::new();
+    let mut exports_main = false;
+    for name in explicit {
+        match defn(&name) {
+            Some((_, expr)) if has_main && name == MAIN => return Err(duplicate_main(expr)),
+            Some(_) => {
+                exports.insert(name);
+            }
+            None if has_main && name == MAIN => exports_main = true,
+            None => return Err(Error::UnknownExport(name)),
+        }
+    }
+    Ok((exports, exports_main))
+}
+
+/// Returns the `name` section, naming every function and its locals.
+fn names(origins: &[Origin]) -> NameSection {
+    let mut functions = NameMap::new();
+    let mut locals = IndirectNameMap::new();
+    for (idx, origin) in origins.iter().enumerate() {
+        functions.append(idx as u32, &origin.name);
+        if !origin.locals.is_empty() {
+            let mut names = NameMap::new();
+            for (local, name) in &origin.locals {
+                names.append(*local, name);
+            }
+            locals.append(idx as u32, &names);
+        }
+    }
+
+    let mut section = NameSection::new();
+    section.functions(&functions);
+    section.locals(&locals);
+    section
+}
+
+/// Returns the name of `name` defined in `namespace`, i.e. `core/map`.
+fn qualified(namespace: &str, name: &str) -> String {
+    format!("{}/{}", namespace, name)
+}
+
+/// Adds all closure functions and the table to call them from.
+fn compile_closures(wasm_module: &mut WasmModule) {
+    let closures = std::mem::take(&mut wasm_module.closures.functions);
+    let wrapped: HashMap<u32, u32> = wasm_module
+        .closures
+        .wrappers
+        .iter()
+        .map(|(idx, table_idx)| (*table_idx, *idx))
+        .collect();
+    let size = closures.len() as u32;
+    for (table_idx, (arity, func, origin)) in closures.into_iter().enumerate() {
+        let origin = origin.unwrap_or_else(|| {
+            let wrapped = &wasm_module.origins[wrapped[&(table_idx as u32)] as usize];
+            Origin::generated(format!("{}/wrapper", wrapped.name))
+        });
+        let type_idx = wasm_module.types.closure(arity);
+        wasm_module.functions.function(type_idx);
+        wasm_module.code.function(&func);
+        wasm_module.origins.push(origin);
+    }
+
+    wasm_module.tables.table(TableType {
+        element_type: RefType::FUNCREF,
+        minimum: size,
+        maximum: Some(size),
+    });
+    let offset = wasm_module.closures.offset;
+    let indices: Vec<u32> = (offset..offset + size).collect();
+    wasm_module.elements.active(
+        Some(0),
+        &ConstExpr::i32_const(0),
+        Elements::Functions(&indices),
+    );
+}
+
+/// Returns the name, parameters and body, if the given expression
+/// is of the form `(defn name (params...) body)`.
+///
+/// Private functions are defined via `defn-` or `(defn ^:private ...)`,
+/// see [`is_private`].
+fn as_defn(expr: &Expr) -> Option<(&str, &[Expr], &Expr)> {
     match expr {
-        Expr::Number { .. } => {}
-        Expr::Symbol { .. } => {}
         Expr::List { expressions, .. } => match expressions.as_slice() {
-            [Expr::Symbol { value, .. }, Expr::Symbol { value: name, .. }, Expr::List {
-                expressions: params,
-                ..
-            }, body]
-                if value == "defn" =>
-            {
-                compile_defn(wasm_module, name, params, body);
+            [Expr::Symbol { value, .. }, rest @ ..] if value == "defn" || value == "defn-" => {
+                let rest = match rest {
+                    [Expr::Symbol { value, .. }, rest @ ..] if value == PRIVATE => rest,
+                    rest => rest,
+                };
+                match rest {
+                    [Expr::Symbol { value: name, .. }, Expr::List {
+                        expressions: params,
+                        ..
+                    }, body] => Some((name, params, body)),
+                    _ => None,
+                }
             }
-            _ => unimplemented!("Unknown form!"),
+            _ => None,
         },
+        _ => None,
     }
 }
 
-fn compile_defn(wasm_module: &mut WasmModule, name: &str, _params: &[Expr], body: &Expr) {
-    let idx = wasm_module.get_and_increment_type_idx();
-    wasm_module.types.function(vec![], vec![ValType::F64]);
-    wasm_module.functions.function(idx);
-    wasm_module.exports.export(name, ExportKind::Func, idx);
+/// Returns whether the given `defn` is private, so it is not exported
+/// unless listed explicitly.
+fn is_private(expr: &Expr) -> bool {
+    match expr {
+        Expr::List { expressions, .. } => match expressions.as_slice() {
+            [Expr::Symbol { value, .. }, ..] if value == "defn-" => true,
+            [_, Expr::Symbol { value, .. }, ..] => value == PRIVATE,
+            _ => false,
+        },
+        _ => false,
+    }
+}
 
-    let mut func = Function::new(vec![]);
-    for instr in compile_instructions(&body) {
-        func.instruction(&instr);
+/// Returns the listed names, if the given expression is of the form
+/// `(export names...)`.
+fn as_export(expr: &Expr) -> Option<Vec<&str>> {
+    match expr {
+        Expr::List { expressions, .. } => match expressions.as_slice() {
+            [Expr::Symbol { value, .. }, names @ ..] if value == "export" => Some(
+                names
+                    .iter()
+                    .filter_map(|name| match name {
+                        Expr::Symbol { value, .. } => Some(value.as_str()),
+                        _ => None,
+                    })
+                    .collect(),
+            ),
+            _ => None,
+        },
+        _ => None,
     }
-    func.instruction(&Instruction::End);
-    wasm_module.code.function(&func);
 }
 
-fn compile_instructions(expr: &Expr) -> Vec<Instruction> {
-    let mut instructions = vec![];
+/// Returns the name and body, if the given expression is of the form
+/// `(deftest name body...)`.
+fn as_deftest(expr: &Expr) -> Option<(&str, &[Expr])> {
     match expr {
         Expr::List { expressions, .. } => match expressions.as_slice() {
-            [Expr::Symbol { value, .. }, args @ ..] => {
-                let mut instrs = compile_expr_with_args(value, args);
-                instructions.append(&mut instrs);
+            [Expr::Symbol { value, .. }, Expr::Symbol { value: name, .. }, body @ ..]
+                if value == "deftest" =>
+            {
+                Some((name, body))
             }
-            _ => {}
+            _ => None,
         },
-        Expr::Number { value, .. } => instructions.push(Instruction::F64Const(*value)),
-        Expr::Symbol { .. } => {}
+        _ => None,
     }
+}
 
-    instructions
+/// Returns whether the given expression is evaluated as part of the
+/// top-level expressions of its namespace, i.e. it is no declaration or
+/// definition.
+fn is_top_level(expr: &Expr) -> bool {
+    !(project::is_ns(expr)
+        || is_import(expr)
+        || as_export(expr).is_some()
+        || as_defn(expr).is_some()
+        || as_deftest(expr).is_some())
+}
+
+/// Returns whether the given expression is an `import` declaration.
+///
+/// Which functions are imported is defined by [`Options::imports`]
+/// and [`HOST_MODULES`], see [`imports`].
+fn is_import(expr: &Expr) -> bool {
+    match expr {
+        Expr::List { expressions, .. } => matches!(
+            expressions.first(),
+            Some(Expr::Symbol { namespace, value, .. }) if namespace.is_empty() && value == "import"
+        ),
+        _ => false,
+    }
+}
+
+/// Returns the functions declared by [`Options::imports`], followed by
+/// those of the host modules the namespaces import, whose functions the
+/// options do not declare.
+fn imports(namespaces: &[Namespace], options: &Options) -> Vec<Import> {
+    let mut imports = options.imports.clone();
+    let modules = namespaces
+        .iter()
+        .flat_map(|namespace| &namespace.module.expressions)
+        .filter(|expr| is_import(expr))
+        .filter_map(|expr| match expr {
+            Expr::List { expressions, .. } => Some(&expressions[1..]),
+            _ => None,
+        })
+        .flatten();
+    for module in modules {
+        let Expr::Symbol { value, .. } = module else {
+            continue;
+        };
+        if !imports.iter().any(|import| &import.module == value) {
+            imports.extend(host_module(value).unwrap_or_default());
+        }
+    }
+    imports
+}
+
+/// A function defined in source code, which has been lowered but not
+/// compiled yet.
+struct Body {
+    idx: u32,
+    function: ir::Function,
+    origin: Origin,
+    /// Whether the function is a `defn`, which may be inlined.
+    is_defn: bool,
+    /// Whether the function returns a value, which only the start
+    /// function does not.
+    returns: bool,
+    /// The test, if it is one, whose assertions are collected while
+    /// compiling it.
+    test: Option<Test>,
+}
+
+fn lower_expr(
+    expr: &Expr,
+    namespace: &Namespace,
+    aliases: &HashMap<String, String>,
+    exported: &dyn Fn(&str) -> bool,
+    wasm_module: &mut WasmModule,
+) -> Option<Body> {
+    match as_defn(expr) {
+        Some((name, params, body)) => {
+            // Unreachable functions have not been assigned an index.
+            let idx = wasm_module
+                .defns
+                .get(&qualified(&namespace.name, name))?
+                .idx;
+            if exported(name) {
+                wasm_module.exports.export(name, ExportKind::Func, idx);
+            }
+            let env = lower::Env {
+                namespace: &namespace.name,
+                aliases,
+                defns: &wasm_module.defns,
+                builtins: wasm_module.builtins,
+            };
+            let origin = Origin {
+                name: qualified(&namespace.name, name),
+                filename: namespace.module.filename.clone(),
+                region: Some(*expr.region()),
+                locals: vec![],
+                offsets: vec![],
+            };
+            Some(Body {
+                idx,
+                function: lower::function(env, params, body),
+                origin,
+                is_defn: true,
+                returns: true,
+                test: None,
+            })
+        }
+        None => {
+            let (name, body) = as_deftest(expr)?;
+            lower_test(wasm_module, namespace, aliases, expr.region(), name, body)
+        }
+    }
+}
+
+/// Lowers the top-level expressions of a namespace into a function
+/// evaluating them in order, which returns the value of the last one.
+///
+/// The function of the entry namespace evaluates the top-level
+/// expressions of the namespaces it requires first. If it is the start
+/// function, it does not return anything.
+fn lower_script(
+    wasm_module: &mut WasmModule,
+    namespace: &Namespace,
+    aliases: &HashMap<String, String>,
+    is_entry: bool,
+    start: bool,
+) -> Option<Body> {
+    let idx = wasm_module
+        .scripts
+        .iter()
+        .find(|(name, _)| *name == namespace.name)
+        .map(|(_, idx)| *idx)?;
+    let expressions: Vec<&Expr> = namespace
+        .module
+        .expressions
+        .iter()
+        .filter(|expr| is_top_level(expr))
+        .collect();
+
+    let mut prefix = vec![];
+    if is_entry {
+        for (_, script) in wasm_module
+            .scripts
+            .iter()
+            .filter(|(_, script)| *script != idx)
+        {
+            prefix.push(ir::Expr::generated(ir::Kind::Call {
+                function: *script,
+                args: vec![],
+            }));
+        }
+    }
+
+    let returns = !(is_entry && start);
+    if !returns {
+        wasm_module.start = Some(idx);
+    }
+
+    let env = lower::Env {
+        namespace: &namespace.name,
+        aliases,
+        defns: &wasm_module.defns,
+        builtins: wasm_module.builtins,
+    };
+    let origin = Origin {
+        name: qualified(&namespace.name, "toplevel"),
+        filename: namespace.module.filename.clone(),
+        region: expressions.first().map(|expr| *expr.region()),
+        locals: vec![],
+        offsets: vec![],
+    };
+    Some(Body {
+        idx,
+        function: lower::sequence(env, prefix, &expressions),
+        origin,
+        is_defn: false,
+        returns,
+        test: None,
+    })
+}
+
+/// Lowers a test, evaluating each expression in its body in order,
+/// if tests are compiled at all.
+fn lower_test(
+    wasm_module: &mut WasmModule,
+    namespace: &Namespace,
+    aliases: &HashMap<String, String>,
+    region: &Region,
+    name: &str,
+    body: &[Expr],
+) -> Option<Body> {
+    let export = qualified(&namespace.name, name);
+    let idx = *wasm_module.test_indices.get(&export)?;
+    wasm_module.exports.export(&export, ExportKind::Func, idx);
+
+    let env = lower::Env {
+        namespace: &namespace.name,
+        aliases,
+        defns: &wasm_module.defns,
+        builtins: wasm_module.builtins,
+    };
+    let body: Vec<&Expr> = body.iter().collect();
+    let mut function = lower::sequence(env, vec![], &body);
+    if let ir::Kind::Do(exprs) = &mut function.body.kind {
+        exprs.push(ir::Expr::generated(ir::Kind::Number(0.0)));
+    }
+
+    let origin = Origin {
+        name: export.clone(),
+        filename: namespace.module.filename.clone(),
+        region: Some(*region),
+        locals: vec![],
+        offsets: vec![],
+    };
+    let test = Test {
+        namespace: namespace.name.clone(),
+        name: name.to_string(),
+        export,
+        filename: namespace.module.filename.clone(),
+        assertions: vec![],
+    };
+    Some(Body {
+        idx,
+        function,
+        origin,
+        is_defn: false,
+        returns: true,
+        test: Some(test),
+    })
+}
+
+fn compile_body(wasm_module: &mut WasmModule, body: &mut Body) {
+    let function = &body.function;
+    let origin = body.origin.clone();
+    let name = origin.name.clone();
+    let filename = origin.filename.clone();
+    let mut ctx = Context::new(
+        &name,
+        filename.as_deref(),
+        &mut wasm_module.closures,
+        &mut wasm_module.types,
+        wasm_module.builtins,
+        function,
+    );
+    if body.test.is_some() {
+        ctx.assertions = Some(vec![]);
+    }
+
+    let mut instructions = compile_as(&function.body, ValType::F64, &mut ctx);
+    let mut results = vec![ValType::F64];
+    if !body.returns {
+        instructions.push(Instruction::Drop);
+        results.clear();
+    }
+    let (func, origin) = ctx.function(instructions, origin, &function.locals);
+    let assertions = ctx.assertions.take().unwrap_or_default();
+
+    let params = vec![ValType::F64; function.params as usize];
+    wasm_module.add_function(params, results, &func, origin);
+    if let Some(mut test) = body.test.take() {
+        test.assertions = assertions;
+        wasm_module.tests.push(test);
+    }
+}
+
+/// Everything known while compiling the body of a function.
+struct Context<'a> {
+    /// The qualified name of the function, which closures defined in
+    /// it are named after.
+    function: &'a str,
+    filename: Option<&'a str>,
+    closures: &'a mut Closures,
+    types: &'a mut Types,
+    /// Index of the first builtin.
+    builtins: u32,
+    /// Types of all locals, including parameters.
+    locals: Vec<ValType>,
+    params: u32,
+    /// Regions of the assertions, if compiling the body of a test.
+    assertions: Option<Vec<Region>>,
+    /// Regions of the expressions marked so far, see [`Context::mark`].
+    marks: Vec<Region>,
+}
+
+impl<'a> Context<'a> {
+    fn new(
+        function: &'a str,
+        filename: Option<&'a str>,
+        closures: &'a mut Closures,
+        types: &'a mut Types,
+        builtins: u32,
+        body: &ir::Function,
+    ) -> Self {
+        Context {
+            function,
+            filename,
+            closures,
+            types,
+            builtins,
+            locals: body
+                .locals
+                .iter()
+                .enumerate()
+                .map(|(idx, local)| match idx < body.params as usize {
+                    true => ValType::F64,
+                    false => repr(local.ty),
+                })
+                .collect(),
+            params: body.params,
+            assertions: None,
+            marks: vec![],
+        }
+    }
+
+    /// Returns a marker for the start of the instructions of an
+    /// expression with the given region.
+    ///
+    /// Markers are `nop`s, which are never emitted otherwise. They are
+    /// replaced with the offset of the next instruction in
+    /// [`Context::function`], relying on the instructions of
+    /// expressions being emitted in the order they are compiled in.
+    fn mark(&mut self, region: Region) -> Instruction<'static> {
+        self.marks.push(region);
+        Instruction::Nop
+    }
+
+    /// Returns the function index of the given builtin.
+    fn builtin(&self, builtin: Builtin) -> u32 {
+        builtin.index(self.builtins)
+    }
+
+    /// Returns the index of a new `f64` local.
+    fn fresh_local(&mut self) -> u32 {
+        self.locals.push(ValType::F64);
+        self.locals.len() as u32 - 1
+    }
+
+    /// Returns the type of the local at the given index.
+    fn local(&self, idx: u32) -> ValType {
+        self.locals[idx as usize]
+    }
+
+    /// Returns a function with the given body and all locals, adding
+    /// the names of its locals and the offsets of its expressions to
+    /// `origin`.
+    fn function(
+        &self,
+        instructions: Vec<Instruction>,
+        mut origin: Origin,
+        locals: &[ir::Local],
+    ) -> (Function, Origin) {
+        let mut func =
+            Function::new_with_locals_types(self.locals[self.params as usize..].iter().copied());
+        let mut marks = self.marks.iter();
+        for instr in instructions {
+            match instr {
+                Instruction::Nop => {
+                    let Some(region) = marks.next() else {
+                        continue;
+                    };
+                    // Nested expressions may start at the same offset,
+                    // where the innermost one is the most precise.
+                    let offset = func.byte_len();
+                    if origin
+                        .offsets
+                        .last()
+                        .is_some_and(|(last, _)| *last == offset)
+                    {
+                        origin.offsets.pop();
+                    }
+                    origin.offsets.push((offset, *region));
+                }
+                instr => {
+                    func.instruction(&instr);
+                }
+            }
+        }
+        func.instruction(&Instruction::End);
+        origin.locals = locals
+            .iter()
+            .enumerate()
+            .filter(|(_, local)| !local.name.is_empty())
+            .map(|(idx, local)| (idx as u32, local.name.clone()))
+            .collect();
+        (func, origin)
+    }
 }
 
-fn compile_expr_with_args<'a>(symbol: &'a str, args: &'a [Expr]) -> Vec<Instruction<'a>> {
-    match symbol {
-        "+" => compile_bin_op(Instruction::F64Add, args),
-        "-" => compile_bin_op(Instruction::F64Sub, args),
-        "*" => compile_bin_op(Instruction::F64Mul, args),
-        "/" => compile_bin_op(Instruction::F64Div, args),
-        "<" => compile_bin_op(Instruction::F64Lt, args),
-        "<=" => compile_bin_op(Instruction::F64Le, args),
-        ">" => compile_bin_op(Instruction::F64Gt, args),
-        ">=" => compile_bin_op(Instruction::F64Ge, args),
+/// Returns how values of the given type are represented at runtime,
+/// see [`ir::Type`].
+fn repr(ty: ir::Type) -> ValType {
+    match ty {
+        ir::Type::Boolean => ValType::I32,
+        _ => ValType::F64,
+    }
+}
+
+/// Returns the instructions converting a value from one representation
+/// into another, where an `f64` is `true`, if it is not `0`.
+fn convert(from: ValType, to: ValType) -> Vec<Instruction<'static>> {
+    match (from, to) {
+        (ValType::I32, ValType::F64) => vec![Instruction::F64ConvertI32U],
+        (ValType::F64, ValType::I32) => vec![Instruction::F64Const(0.0), Instruction::F64Ne],
         _ => vec![],
     }
 }
 
-fn compile_bin_op<'a>(op: Instruction<'a>, args: &'a [Expr]) -> Vec<Instruction<'a>> {
-    // (+ 3 5 6 7) -> (+ (+ (+ 3 5) 6) 7)
-    // const 3
-    // const 5
-    // add
-    // const 6
-    // add
-    // const 7
-    // add
-    match args {
-        [head, rest @ ..] => {
-            let mut instructions = vec![];
-            instructions.append(&mut compile_instructions(&head));
-            for expr in rest {
-                instructions.append(&mut compile_instructions(&expr));
-                instructions.push(op.clone());
+/// Compiles the expression into instructions evaluating to a value of
+/// the given representation.
+fn compile_as(expr: &ir::Expr, to: ValType, ctx: &mut Context) -> Vec<Instruction<'static>> {
+    let mut instructions = compile_instructions(expr, ctx);
+    instructions.append(&mut convert(repr(expr.ty), to));
+    instructions
+}
+
+/// Compiles the expression into instructions evaluating to a value
+/// represented as its type says, see [`repr`].
+fn compile_instructions(expr: &ir::Expr, ctx: &mut Context) -> Vec<Instruction<'static>> {
+    let mut instructions = vec![];
+    if let Some(region) = expr.region {
+        instructions.push(ctx.mark(region));
+    }
+    let repr = repr(expr.ty);
+    // The representation of the value the instructions evaluate to.
+    let actual = match &expr.kind {
+        ir::Kind::Number(value) => {
+            instructions.push(Instruction::F64Const(*value));
+            ValType::F64
+        }
+        ir::Kind::Boolean(value) => {
+            instructions.push(Instruction::I32Const(*value as i32));
+            ValType::I32
+        }
+        ir::Kind::Local(idx) => {
+            instructions.push(Instruction::LocalGet(*idx));
+            ctx.local(*idx)
+        }
+        ir::Kind::Reference { function, arity } => {
+            let table_idx = ctx.closures.wrapper(*function, *arity);
+            instructions.append(&mut compile_closure(table_idx, &[], ctx));
+            ValType::F64
+        }
+        ir::Kind::Binary { op, left, right } => {
+            for operand in [left, right] {
+                instructions.append(&mut compile_as(operand, ValType::F64, ctx));
+                if op.is_bitwise() {
+                    instructions.push(Instruction::I64TruncSatF64S);
+                }
+            }
+            instructions.push(compile_op(*op));
+            if op.is_bitwise() {
+                instructions.push(Instruction::F64ConvertI64S);
+            }
+            if op.is_comparison() {
+                ValType::I32
+            } else {
+                ValType::F64
+            }
+        }
+        ir::Kind::Unary { op, value } => {
+            instructions.append(&mut compile_as(value, ValType::F64, ctx));
+            instructions.append(&mut compile_unary(*op));
+            ValType::F64
+        }
+        ir::Kind::Not(value) => {
+            instructions.append(&mut compile_as(value, ValType::I32, ctx));
+            instructions.push(Instruction::I32Eqz);
+            ValType::I32
+        }
+        ir::Kind::If {
+            cond,
+            then,
+            otherwise,
+        } => {
+            instructions.append(&mut compile_as(cond, ValType::I32, ctx));
+            instructions.push(Instruction::If(BlockType::Result(repr)));
+            instructions.append(&mut compile_as(then, repr, ctx));
+            instructions.push(Instruction::Else);
+            instructions.append(&mut compile_as(otherwise, repr, ctx));
+            instructions.push(Instruction::End);
+            repr
+        }
+        ir::Kind::Let { local, value, body } => {
+            let ty = ctx.local(*local);
+            instructions.append(&mut compile_as(value, ty, ctx));
+            instructions.push(Instruction::LocalSet(*local));
+            instructions.append(&mut compile_as(body, repr, ctx));
+            repr
+        }
+        ir::Kind::Do(exprs) => {
+            for (i, expr) in exprs.iter().enumerate() {
+                if i + 1 < exprs.len() {
+                    instructions.append(&mut compile_instructions(expr, ctx));
+                    instructions.push(Instruction::Drop);
+                } else {
+                    instructions.append(&mut compile_as(expr, repr, ctx));
+                }
+            }
+            if exprs.is_empty() {
+                instructions.push(Instruction::F64Const(0.0));
+                ValType::F64
+            } else {
+                repr
+            }
+        }
+        ir::Kind::Call { function, args } => {
+            for arg in args {
+                instructions.append(&mut compile_as(arg, ValType::F64, ctx));
+            }
+            instructions.push(Instruction::Call(*function));
+            ValType::F64
+        }
+        ir::Kind::CallClosure { callee, args } => {
+            instructions.append(&mut compile_closure_call(callee, args, ctx));
+            ValType::F64
+        }
+        ir::Kind::Closure { function, captured } => {
+            instructions.append(&mut compile_fn(expr.region, function, captured, ctx));
+            ValType::F64
+        }
+        ir::Kind::Assert(failed) => {
+            let region = expr.region.expect("assertions are part of the source code");
+            let failed = compile_as(failed, ValType::I32, ctx);
+            instructions.append(&mut compile_assertion(region, failed, ctx));
+            ValType::F64
+        }
+        ir::Kind::Unbound => repr,
+    };
+    instructions.append(&mut convert(actual, repr));
+
+    instructions
+}
+
+/// Arithmetic evaluates to an `f64`, comparisons to an `i32` and
+/// bitwise operators, whose operands are truncated, to an `i64`.
+fn compile_op(op: ir::Op) -> Instruction<'static> {
+    match op {
+        ir::Op::Add => Instruction::F64Add,
+        ir::Op::Sub => Instruction::F64Sub,
+        ir::Op::Mul => Instruction::F64Mul,
+        ir::Op::Div => Instruction::F64Div,
+        ir::Op::Lt => Instruction::F64Lt,
+        ir::Op::Le => Instruction::F64Le,
+        ir::Op::Gt => Instruction::F64Gt,
+        ir::Op::Ge => Instruction::F64Ge,
+        ir::Op::Eq => Instruction::F64Eq,
+        ir::Op::Ne => Instruction::F64Ne,
+        ir::Op::Min => Instruction::F64Min,
+        ir::Op::Max => Instruction::F64Max,
+        ir::Op::BitAnd => Instruction::I64And,
+        ir::Op::BitOr => Instruction::I64Or,
+        ir::Op::BitXor => Instruction::I64Xor,
+        ir::Op::ShiftLeft => Instruction::I64Shl,
+        ir::Op::ShiftRight => Instruction::I64ShrS,
+    }
+}
+
+fn compile_unary(op: ir::Unary) -> Vec<Instruction<'static>> {
+    match op {
+        ir::Unary::Neg => vec![Instruction::F64Neg],
+        ir::Unary::Abs => vec![Instruction::F64Abs],
+        ir::Unary::Sqrt => vec![Instruction::F64Sqrt],
+        ir::Unary::Floor => vec![Instruction::F64Floor],
+        ir::Unary::Ceil => vec![Instruction::F64Ceil],
+        ir::Unary::Trunc => vec![Instruction::F64Trunc],
+        ir::Unary::BitNot => vec![
+            Instruction::I64TruncSatF64S,
+            Instruction::I64Const(-1),
+            Instruction::I64Xor,
+            Instruction::F64ConvertI64S,
+        ],
+    }
+}
+
+/// Compiles the function of a closure to a function in the table,
+/// which loads every captured variable from the closure into a local
+/// before evaluating the body.
+fn compile_fn(
+    region: Option<Region>,
+    function: &ir::Function,
+    captured: &[u32],
+    ctx: &mut Context,
+) -> Vec<Instruction<'static>> {
+    let mut fn_ctx = Context::new(
+        ctx.function,
+        ctx.filename,
+        ctx.closures,
+        ctx.types,
+        ctx.builtins,
+        function,
+    );
+
+    let env = 0;
+    let mut instructions = vec![];
+    for i in 0..captured.len() {
+        instructions.push(Instruction::LocalGet(env));
+        instructions.push(Instruction::I32TruncF64U);
+        instructions.push(Instruction::F64Load(mem_arg(8 * (i as u64 + 1))));
+        let local = function.params + i as u32;
+        instructions.append(&mut convert(ValType::F64, fn_ctx.local(local)));
+        instructions.push(Instruction::LocalSet(local));
+    }
+    instructions.append(&mut compile_as(&function.body, ValType::F64, &mut fn_ctx));
+
+    let origin = Origin {
+        name: format!("{}/fn", ctx.function),
+        filename: ctx.filename.map(String::from),
+        region,
+        locals: vec![],
+        offsets: vec![],
+    };
+    let (func, origin) = fn_ctx.function(instructions, origin, &function.locals);
+    let arity = function.params as usize - 1;
+    let table_idx = ctx.closures.add(arity, func, Some(origin));
+    compile_closure(table_idx, captured, ctx)
+}
+
+/// Allocates a closure for the function at `table_idx`, capturing the
+/// values of the given locals.
+fn compile_closure(
+    table_idx: u32,
+    captured: &[u32],
+    ctx: &mut Context,
+) -> Vec<Instruction<'static>> {
+    let closure = ctx.fresh_local();
+    let mut instructions = vec![
+        Instruction::I32Const(8 * (captured.len() as i32 + 1)),
+        Instruction::Call(ctx.builtin(Builtin::Alloc)),
+        Instruction::F64ConvertI32U,
+        Instruction::LocalTee(closure),
+        Instruction::I32TruncF64U,
+        Instruction::F64Const(table_idx as f64),
+        Instruction::F64Store(mem_arg(0)),
+    ];
+    for (i, local) in captured.iter().enumerate() {
+        instructions.push(Instruction::LocalGet(closure));
+        instructions.push(Instruction::I32TruncF64U);
+        instructions.push(Instruction::LocalGet(*local));
+        instructions.append(&mut convert(ctx.local(*local), ValType::F64));
+        instructions.push(Instruction::F64Store(mem_arg(8 * (i as u64 + 1))));
+    }
+    instructions.push(Instruction::LocalGet(closure));
+    instructions
+}
+
+/// Calls the closure `callee` evaluates to with the given arguments.
+fn compile_closure_call(
+    callee: &ir::Expr,
+    args: &[ir::Expr],
+    ctx: &mut Context,
+) -> Vec<Instruction<'static>> {
+    let closure = ctx.fresh_local();
+    let mut instructions = compile_as(callee, ValType::F64, ctx);
+    instructions.push(Instruction::LocalTee(closure));
+    for arg in args {
+        instructions.append(&mut compile_as(arg, ValType::F64, ctx));
+    }
+    instructions.push(Instruction::LocalGet(closure));
+    instructions.push(Instruction::I32TruncF64U);
+    instructions.push(Instruction::F64Load(mem_arg(0)));
+    instructions.push(Instruction::I32TruncF64U);
+    instructions.push(Instruction::CallIndirect {
+        ty: ctx.types.closure(args.len()),
+        table: 0,
+    });
+    instructions
+}
+
+/// Given the instructions evaluating to whether an assertion failed as
+/// an `i32`, returns from the test with the position of the assertion,
+/// if it did.
+///
+/// Outside of a test, the assertion just evaluates to `1`, if it
+/// passed, and `0` otherwise.
+fn compile_assertion(
+    region: Region,
+    mut instructions: Vec<Instruction<'static>>,
+    ctx: &mut Context,
+) -> Vec<Instruction<'static>> {
+    match &mut ctx.assertions {
+        Some(assertions) => {
+            assertions.push(region);
+            instructions.push(Instruction::If(BlockType::Empty));
+            instructions.push(Instruction::F64Const(assertions.len() as f64));
+            instructions.push(Instruction::Return);
+            instructions.push(Instruction::End);
+            instructions.push(Instruction::F64Const(1.0));
+        }
+        None => {
+            instructions.push(Instruction::I32Eqz);
+            instructions.push(Instruction::F64ConvertI32U);
+        }
+    }
+    instructions
+}
+
+fn mem_arg(offset: u64) -> MemArg {
+    MemArg {
+        offset,
+        align: 3,
+        memory_index: 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::compile::{
+        codegen, compile, compile_project, compile_source, compile_tests, ir, Import, OptLevel,
+        Options,
+    };
+    use crate::parse::parse;
+    use crate::project::error::Error;
+    use crate::project::{load, Namespace};
+    use crate::reporting::Region;
+    use std::collections::HashMap;
+    use wasmi::{Caller, Engine, Instance, Linker, Module, Store, WasmParams};
+
+    #[test]
+    fn compile_list_functions() {
+        let source = r#"
+            (defn xs () (list 1 2 3))
+            (defn sum (xs) (if (empty? xs) 0 (+ (first xs) (sum (rest xs)))))
+            (defn total () (sum (cons 4 (xs))))
+            (defn size () (count (xs)))
+            (defn second () (nth (xs) 1))
+            (defn none () (first (list)))
+        "#;
+        let (mut store, instance) = instantiate(source);
+
+        assert_eq!(10.0, call(&mut store, &instance, "total", ()));
+        assert_eq!(3.0, call(&mut store, &instance, "size", ()));
+        assert_eq!(2.0, call(&mut store, &instance, "second", ()));
+        assert_eq!(0.0, call(&mut store, &instance, "none", ()));
+    }
+
+    #[test]
+    fn compile_closures() {
+        let source = r#"
+            (defn twice (f x) (f (f x)))
+            (defn square (x) (* x x))
+            (defn add-twice (n x) (twice (fn (y) (+ y n)) x))
+            (defn square-twice (x) (twice square x))
+            (defn adder (n) (let (m (* n 2)) (fn (x) (fn (y) (+ x y m)))))
+            (defn curried (a b c) (((adder a) b) c))
+            (defn map (f xs) (if (empty? xs) (list) (cons (f (first xs)) (map f (rest xs)))))
+            (defn reduce (f acc xs) (if (empty? xs) acc (reduce f (f acc (first xs)) (rest xs))))
+            (defn doubled () (reduce (fn (a b) (+ a b)) 0 (map (fn (x) (* x 2)) (list 1 2 3))))
+            (defn lengths () (reduce (fn (a b) (+ a b)) 0 (map count (list (list 1) (list 1 2)))))
+        "#;
+        let (mut store, instance) = instantiate(source);
+
+        assert_eq!(11.0, call(&mut store, &instance, "add-twice", (5.0, 1.0)));
+        assert_eq!(81.0, call(&mut store, &instance, "square-twice", 3.0));
+        assert_eq!(9.0, call(&mut store, &instance, "curried", (1.0, 2.0, 5.0)));
+        assert_eq!(12.0, call(&mut store, &instance, "doubled", ()));
+        assert_eq!(3.0, call(&mut store, &instance, "lengths", ()));
+    }
+
+    #[test]
+    fn compile_numeric_operators() {
+        let source = r#"
+            (defn negated (x) (- x))
+            (defn inverse (x) (/ x))
+            (defn single (x) (+ (* x) (min x) (max x)))
+            (defn modulo (a b) (mod a b))
+            (defn remainder (a b) (rem a b))
+            (defn quotient (a b) (quot a b))
+            (defn extremes (a b c) (- (max a b c) (min a b c)))
+            (defn rounding (x) (+ (* 100 (floor x)) (* 10 (ceil x)) (abs (neg x))))
+            (defn root (x) (sqrt x))
+            (defn steps (x) (* (inc x) (dec x)))
+            (defn bits (a b) (+ (bit-and a b) (bit-or a b) (bit-xor a b 1) (bit-not a)))
+            (defn shifts (x) (+ (bit-shift-left x 4) (bit-shift-right x 1)))
+            (defn equal (a b) (= a b))
+            (defn mapped () (reduce max 0 (map inc (list 1 3 2))))
+            (defn folded () (+ (mod (- 7) 3) (bit-shift-left 5 4) (quot 7 2) (sqrt 16)))
+        "#;
+        for opt_level in [OptLevel::O0, OptLevel::O1, OptLevel::O2] {
+            let options = Options {
+                opt_level,
+                ..Options::default()
+            };
+            let bytes = compile_source(None, source, &options).unwrap().bytes;
+            let (mut store, instance) = instantiate_bytes(&bytes);
+
+            assert_eq!(-5.0, call(&mut store, &instance, "negated", 5.0));
+            assert_eq!(0.5, call(&mut store, &instance, "inverse", 2.0));
+            assert_eq!(9.0, call(&mut store, &instance, "single", 3.0));
+            assert_eq!(2.0, call(&mut store, &instance, "modulo", (-7.0, 3.0)));
+            assert_eq!(-2.0, call(&mut store, &instance, "modulo", (7.0, -3.0)));
+            assert_eq!(-1.0, call(&mut store, &instance, "remainder", (-7.0, 3.0)));
+            assert_eq!(1.0, call(&mut store, &instance, "remainder", (7.0, -3.0)));
+            assert_eq!(-2.0, call(&mut store, &instance, "quotient", (-7.0, 3.0)));
+            assert_eq!(
+                8.0,
+                call(&mut store, &instance, "extremes", (5.0, -1.0, 7.0))
+            );
+            assert_eq!(121.5, call(&mut store, &instance, "rounding", 1.5));
+            assert_eq!(3.0, call(&mut store, &instance, "root", 9.0));
+            assert_eq!(15.0, call(&mut store, &instance, "steps", 4.0));
+            assert_eq!(6.0, call(&mut store, &instance, "bits", (6.0, 3.0)));
+            assert_eq!(82.0, call(&mut store, &instance, "shifts", 5.0));
+            assert_eq!(1.0, call(&mut store, &instance, "equal", (2.0, 2.0)));
+            assert_eq!(4.0, call(&mut store, &instance, "mapped", ()));
+            assert_eq!(89.0, call(&mut store, &instance, "folded", ()));
+        }
+    }
+
+    #[test]
+    fn compile_booleans() {
+        let source = r#"
+            (defn forever () (forever))
+            (defn between (x lo hi) (and (<= lo x) (< x hi)))
+            (defn sign (x) (if (< x 0) (- 0 1) (if (= x 0) 0 1)))
+            (defn none () (list (and) (or) (not false)))
+            (defn logic () (list (and true false) (or false 0 7) (not 2)))
+            (defn lazy () (list (and false (forever)) (or true (forever)) (< 2 1 (forever))))
+            (defn chained (a b c) (list (< a b c) (<= a b c) (= a b c) (not= a b c) (> a) (>= c b a)))
+            (defn ascending () (chained 1 2 2))
+            (defn same () (chained 2 2 2))
+            (defn flags (x) (let (big (> x 9) odd (not= x 2)) (fn () (if big odd (not odd)))))
+            (defn called (x) ((flags x)))
+            (defn truthy (x) (if x 1 2))
+        "#;
+        for opt_level in [OptLevel::O0, OptLevel::O1, OptLevel::O2] {
+            let options = Options {
+                opt_level,
+                ..Options::default()
+            };
+            let bytes = compile_source(None, source, &options).unwrap().bytes;
+            let (mut store, instance) = instantiate_bytes(&bytes);
+            let mut list = |name: &str| {
+                let mut list = call(&mut store, &instance, name, ());
+                let mut values = vec![];
+                let memory = instance.get_memory(&store, "memory").unwrap();
+                while list != 0.0 {
+                    let mut cell = [0; 16];
+                    memory.read(&store, list as usize, &mut cell).unwrap();
+                    values.push(f64::from_le_bytes(cell[..8].try_into().unwrap()));
+                    list = f64::from_le_bytes(cell[8..].try_into().unwrap());
+                }
+                values
+            };
+
+            assert_eq!(vec![1.0, 0.0, 1.0], list("none"));
+            assert_eq!(vec![0.0, 7.0, 0.0], list("logic"));
+            assert_eq!(vec![0.0, 1.0, 0.0], list("lazy"));
+            assert_eq!(vec![0.0, 1.0, 0.0, 1.0, 1.0, 1.0], list("ascending"));
+            assert_eq!(vec![0.0, 1.0, 1.0, 0.0, 1.0, 1.0], list("same"));
+            assert_eq!(1.0, call(&mut store, &instance, "between", (3.0, 1.0, 5.0)));
+            assert_eq!(0.0, call(&mut store, &instance, "between", (5.0, 1.0, 5.0)));
+            assert_eq!(-1.0, call(&mut store, &instance, "sign", -3.0));
+            assert_eq!(0.0, call(&mut store, &instance, "sign", 0.0));
+            assert_eq!(1.0, call(&mut store, &instance, "called", 10.0));
+            assert_eq!(1.0, call(&mut store, &instance, "called", 2.0));
+            assert_eq!(0.0, call(&mut store, &instance, "called", 3.0));
+            assert_eq!(2.0, call(&mut store, &instance, "truthy", 0.0));
+            assert_eq!(1.0, call(&mut store, &instance, "truthy", 0.5));
+        }
+    }
+
+    #[test]
+    fn compile_with_core() {
+        let source = r#"
+            (defn add (a b) (+ a b))
+            (defn succ (x) (+ x 1))
+            (defn sum (xs) (reduce add 0 xs))
+            (defn small () (count (filter (fn (x) (< x 2)) (range 0 5))))
+            (defn odds () (sum (core/map (comp succ (partial add 1)) (range 0 3))))
+            (defn applied () (apply add (list 4 5)))
+            (defn add5 (a b c d e) (+ a b c d e))
+            (defn applied5 () (apply add5 (list 1 2 3 4 5)))
+            (defn misapplied () (apply add (list 1)))
+            (defn operators () (+ (apply * (list 2 3)) (reduce + 0 (list 1 2 3)) (count (filter (partial < 1) (list 1 2 3)))))
+            (defn map (xs) (core/map succ xs))
+            (defn mapped () (sum (map (list 1 2))))
+            (defn wrapped () (sum (core/map sqrt (core/map inc (list 3 8)))))
+            (defn masked () (reduce bit-or 0 (list 1 2 (bit-not (bit-not 4)))))
+            (defn negated () (count (filter not (list true false false))))
+        "#;
+        let (mut store, instance) = instantiate(source);
+
+        assert_eq!(2.0, call(&mut store, &instance, "small", ()));
+        assert_eq!(9.0, call(&mut store, &instance, "odds", ()));
+        assert_eq!(9.0, call(&mut store, &instance, "applied", ()));
+        assert_eq!(15.0, call(&mut store, &instance, "applied5", ()));
+        assert_eq!(14.0, call(&mut store, &instance, "operators", ()));
+        let misapplied = instance
+            .get_typed_func::<(), f64>(&store, "misapplied")
+            .unwrap();
+        assert!(misapplied.call(&mut store, ()).is_err());
+        assert_eq!(5.0, call(&mut store, &instance, "mapped", ()));
+        assert_eq!(5.0, call(&mut store, &instance, "wrapped", ()));
+        assert_eq!(7.0, call(&mut store, &instance, "masked", ()));
+        assert_eq!(2.0, call(&mut store, &instance, "negated", ()));
+        assert!(instance.get_func(&store, "reduce").is_none());
+    }
+
+    #[test]
+    fn compile_multiple_namespaces() {
+        let sources: HashMap<String, String> = [(
+            "my.util".to_string(),
+            "(ns my.util) (defn double (x) (* x 2))".to_string(),
+        )]
+        .into();
+        let entry = r#"
+            (ns my.app (:require [my.util :as u]))
+            (defn quad (x) (u/double (my.util/double x)))
+        "#;
+        let entry = Namespace::new(parse(None, entry).unwrap()).unwrap();
+        let bytes = codegen(&load(entry, &sources).unwrap(), &Options::default())
+            .unwrap()
+            .bytes;
+        let (mut store, instance) = instantiate_bytes(&bytes);
+
+        assert_eq!(12.0, call(&mut store, &instance, "quad", 3.0));
+        assert!(instance.get_func(&store, "double").is_none());
+    }
+
+    #[test]
+    fn compile_with_imports_and_exports() {
+        let source = r#"
+            (import io)
+            (defn twice (x) (* 2 (io/log x)))
+            (defn main () (twice 21))
+        "#;
+        let module = parse(None, source).unwrap();
+        let namespaces = load(Namespace::new(module).unwrap(), &HashMap::new()).unwrap();
+        let options = Options {
+            exports: Some(vec!["main".to_string()]),
+            imports: vec![Import {
+                module: "io".to_string(),
+                name: "log".to_string(),
+                arity: 1,
+            }],
+            ..Options::default()
+        };
+        let bytes = codegen(&namespaces, &options).unwrap().bytes;
+
+        let engine = Engine::default();
+        let module = Module::new(&engine, &bytes[..]).unwrap();
+        let mut store = Store::new(&engine, ());
+        let mut linker = Linker::new(&engine);
+        linker.func_wrap("io", "log", |x: f64| x + 1.0).unwrap();
+        let instance = linker
+            .instantiate(&mut store, &module)
+            .unwrap()
+            .start(&mut store)
+            .unwrap();
+
+        assert_eq!(44.0, call(&mut store, &instance, "main", ()));
+        assert!(instance.get_func(&store, "twice").is_none());
+
+        let options = Options {
+            exports: Some(vec!["missing".to_string()]),
+            imports: vec![],
+            ..Options::default()
+        };
+        assert!(matches!(
+            codegen(&namespaces, &options),
+            Err(Error::UnknownExport(name)) if name == "missing"
+        ));
+    }
+
+    #[test]
+    fn compile_tests_with_assertions() {
+        let sources: HashMap<String, String> = [(
+            "my.test".to_string(),
+            r#"(ns my.test)
+(defn succ (x) (+ x 1))
+(deftest passing (is (< 1 2)) (assert= 2 (succ 1)))
+(deftest failing
+  (is 1)
+  (assert= 3 (succ 1)))"#
+                .to_string(),
+        )]
+        .into_iter()
+        .collect();
+
+        let output = compile_tests(&["my.test"], &sources, &Options::default()).unwrap();
+        let names: Vec<&str> = output
+            .tests
+            .iter()
+            .map(|test| test.export.as_str())
+            .collect();
+        assert_eq!(vec!["my.test/passing", "my.test/failing"], names);
+        assert_eq!(Region::from((6, 3, 22)), output.tests[1].assertions[1]);
+
+        let (mut store, instance) = instantiate_bytes(&output.bytes);
+        assert_eq!(0.0, call(&mut store, &instance, "my.test/passing", ()));
+        assert_eq!(2.0, call(&mut store, &instance, "my.test/failing", ()));
+        assert!(instance.get_func(&store, "succ").is_none());
+
+        let output = compile_project("my.test", &sources, &Options::default()).unwrap();
+        let (store, instance) = instantiate_bytes(&output.bytes);
+        assert!(instance.get_func(&store, "my.test/passing").is_none());
+    }
+
+    #[test]
+    fn compile_reports_unbound_symbols() {
+        let source = "(defn double (x) (* 2 x))\n(defn broken (x)\n  (doubel x))";
+        match compile(Some("broken.edn".to_string()), source) {
+            Err(Error::Unbound(filename, region, name, suggestion)) => {
+                assert_eq!(Some("broken.edn".to_string()), filename);
+                assert_eq!(Region::from((3, 4, 9)), region);
+                assert_eq!("doubel", name);
+                assert_eq!(Some("double".to_string()), suggestion);
             }
+            result => panic!("Expected an unbound symbol, got {:?}", result),
+        }
 
-            instructions
+        let sources: HashMap<String, String> = [
+            (
+                "my.util".to_string(),
+                "(ns my.util) (defn double (x) (* 2 x))".to_string(),
+            ),
+            (
+                "my.app".to_string(),
+                "(ns my.app (:require [my.util :as u]))\n(defn quad (y) (u/dubble (+ y z)))"
+                    .to_string(),
+            ),
+        ]
+        .into();
+        assert!(matches!(
+            compile_project("my.app", &sources, &Options::default()),
+            Err(Error::Unbound(_, _, name, Some(suggestion)))
+                if name == "u/dubble" && suggestion == "u/double"
+        ));
+
+        let source = "(defn f (count) (let (n 1) (fn (x) (+ x n count (first (list))))))\n(f y)";
+        assert!(matches!(
+            compile(None, source),
+            Err(Error::Unbound(_, region, name, None)) if name == "y" && region == Region::from((2, 4, 4))
+        ));
+    }
+
+    #[test]
+    fn compile_reports_malformed_forms() {
+        let bad_form = |source: &str| match compile(None, source) {
+            Err(Error::BadForm(_, region, message)) => (region, message),
+            result => panic!("Expected a malformed form, got {:?}", result),
+        };
+
+        assert_eq!(
+            (
+                Region::from((1, 1, 20)),
+                "defn expects a name, a list of parameters and a body".to_string()
+            ),
+            bad_form("(defn foo x (+ 1 2))")
+        );
+        assert_eq!(
+            (
+                Region::from((1, 1, 14)),
+                "defn expects a name, a list of parameters and a body".to_string()
+            ),
+            bad_form("(defn foo (x))")
+        );
+        assert_eq!(
+            (
+                Region::from((1, 12, 12)),
+                "parameters must be symbols".to_string()
+            ),
+            bad_form("(defn foo (1) 2)")
+        );
+        assert_eq!(
+            (
+                Region::from((1, 1, 11)),
+                "def is not supported, use defn instead".to_string()
+            ),
+            bad_form("(def foo 3)")
+        );
+        assert_eq!(
+            (
+                Region::from((1, 18, 20)),
+                "+ expects at least one argument".to_string()
+            ),
+            bad_form("(defn foo (x) (- (+) x))")
+        );
+        assert_eq!(
+            (
+                Region::from((1, 1, 6)),
+                "if expects a condition and one or two branches".to_string()
+            ),
+            bad_form("(if 1)")
+        );
+        assert_eq!(
+            (
+                Region::from((1, 11, 11)),
+                "let expects pairs of a symbol and a value".to_string()
+            ),
+            bad_form("(let (x 1 2 3) x)")
+        );
+        assert_eq!(
+            (
+                Region::from((1, 15, 27)),
+                "import is only allowed at the top level".to_string()
+            ),
+            bad_form("(defn foo (x) (import math))")
+        );
+    }
+
+    #[test]
+    fn compile_reports_duplicate_definitions() {
+        for source in [
+            "(defn f () 1)\n(defn f () 2)",
+            "(defn- f () 1)\n(defn- f () 2)",
+        ] {
+            let len = source.find('\n').unwrap();
+            let error = compile(Some("f.edn".to_string()), source).unwrap_err();
+            assert!(matches!(
+                &error,
+                Error::Duplicate(_, region, name, first)
+                    if name == "f"
+                        && *region == Region::from((2, 1, len))
+                        && *first == Region::from((1, 1, len))
+            ));
+            let diagnostic = error.to_diagnostic();
+            assert_eq!("W0012", diagnostic.code);
+            assert_eq!(Region::from((1, 1, len)), diagnostic.labels[0].region);
         }
-        _ => panic!("That's bad man."),
+    }
+
+    #[test]
+    fn compile_reports_redefined_special_forms() {
+        let source = "(defn inc (x) (+ x 1))";
+        assert!(matches!(
+            compile(None, source),
+            Err(Error::BadForm(_, region, message))
+                if region == Region::from((1, 7, 9))
+                    && message == "`inc` is a special form and cannot be redefined"
+        ));
+    }
+
+    #[test]
+    fn compile_reports_wrong_arity() {
+        let source = "(defn double (x) (* 2 x))\n(double 1 2)";
+        assert!(matches!(
+            compile(None, source),
+            Err(Error::Arity(_, region, name, 1, 2))
+                if name == "double" && region == Region::from((2, 1, 12))
+        ));
+
+        assert!(matches!(
+            compile(None, "(cons 1)"),
+            Err(Error::Arity(_, _, name, 2, 1)) if name == "cons"
+        ));
+
+        // Locals may shadow functions with a different arity.
+        let source = "(defn double (x) (* 2 x))\n(defn f (double) (double 1 2))";
+        assert!(compile(None, source).is_ok());
+    }
+
+    #[test]
+    fn compile_reports_lexer_errors() {
+        let diagnostic = compile(None, "(+ 1 @ 2)").unwrap_err().to_diagnostic();
+        assert_eq!("W0001", diagnostic.code);
+        assert_eq!(Some(Region::from((1, 6))), diagnostic.region);
+    }
+
+    #[test]
+    fn compile_names_and_source_map() {
+        let source = "(defn add (x y)\n  (let (z (+ x y)) z))";
+        let module = parse(Some("add.edn".to_string()), source).unwrap();
+        let namespaces = load(Namespace::new(module).unwrap(), &HashMap::new()).unwrap();
+        let output = codegen(&namespaces, &Options::default()).unwrap();
+
+        let (functions, locals) = names(&output.bytes);
+        let add = functions
+            .iter()
+            .find(|(_, name)| *name == "user/add")
+            .unwrap()
+            .0;
+        let add = locals.iter().find(|(idx, _)| *idx == add).unwrap();
+        assert_eq!(vec!["x", "y", "z"], add.1);
+        assert!(functions.iter().any(|(_, name)| *name == "runtime/cons"));
+        assert_eq!("add.edn", output.source_map.sources.last().unwrap());
+        assert!(!output.source_map.mappings.is_empty());
+    }
+
+    #[test]
+    fn compile_top_level_expressions() {
+        let sources: HashMap<String, String> = [
+            ("my.util".to_string(), "(ns my.util)\n(io/log 1)".to_string()),
+            (
+                "my.app".to_string(),
+                "(ns my.app (:require my.util))\n(defn double (x) (* 2 x))\n(io/log (double 2))\n(double 21)"
+                    .to_string(),
+            ),
+        ]
+        .into_iter()
+        .collect();
+        let log = Import {
+            module: "io".to_string(),
+            name: "log".to_string(),
+            arity: 1,
+        };
+        let instantiate = |options: &Options| {
+            let output = compile_project("my.app", &sources, options).unwrap();
+            let engine = Engine::default();
+            let module = Module::new(&engine, &output.bytes[..]).unwrap();
+            let mut store = Store::new(&engine, vec![]);
+            let mut linker = Linker::new(&engine);
+            linker
+                .func_wrap("io", "log", |mut caller: Caller<'_, Vec<f64>>, x: f64| {
+                    caller.data_mut().push(x);
+                    x
+                })
+                .unwrap();
+            let instance = linker
+                .instantiate(&mut store, &module)
+                .unwrap()
+                .start(&mut store)
+                .unwrap();
+            (store, instance)
+        };
+
+        let options = Options {
+            imports: vec![log.clone()],
+            ..Options::default()
+        };
+        let (mut store, instance) = instantiate(&options);
+        assert!(store.data().is_empty());
+        let main = instance.get_typed_func::<(), f64>(&store, "main").unwrap();
+        assert_eq!(42.0, main.call(&mut store, ()).unwrap());
+        assert_eq!(&vec![1.0, 4.0], store.data());
+
+        let options = Options {
+            imports: vec![log],
+            start: true,
+            ..Options::default()
+        };
+        let (store, instance) = instantiate(&options);
+        assert_eq!(&vec![1.0, 4.0], store.data());
+        assert!(instance.get_func(&store, "main").is_none());
+
+        assert!(matches!(
+            compile(None, "(defn main () 1)\n(main)"),
+            Err(Error::DuplicateMain(_, region, Some(script)))
+                if region == Region::from((1, 1, 16)) && script == Region::from((2, 1, 6))
+        ));
+    }
+
+    #[test]
+    fn compile_only_exported_and_reachable_functions() {
+        let source = r#"
+            (defn- helper (x) (* 2 x))
+            (defn ^:private unused () 1)
+            (defn twice (x) (helper x))
+            (defn dead () (unused))
+        "#;
+        let (mut store, instance) = instantiate(source);
+        assert_eq!(6.0, call(&mut store, &instance, "twice", 3.0));
+        assert!(instance.get_func(&store, "helper").is_none());
+        assert!(instance.get_func(&store, "unused").is_none());
+        assert!(instance.get_func(&store, "dead").is_some());
+
+        let source = format!("{}\n(export twice)", source);
+        let bytes = compile(None, &source).unwrap();
+        let (functions, _) = names(&bytes);
+        let functions: Vec<&str> = functions.into_iter().map(|(_, name)| name).collect();
+        assert!(functions.contains(&"user/helper"));
+        assert!(!functions.contains(&"user/dead"));
+        assert!(!functions.contains(&"user/unused"));
+        assert!(!functions.contains(&"core/map"));
+        let (store, instance) = instantiate_bytes(&bytes);
+        assert!(instance.get_func(&store, "dead").is_none());
+    }
+
+    #[test]
+    fn compile_with_optimizations() {
+        let source = r#"
+            (defn double (x) (* 2 x))
+            (defn fact (n) (if (< n 1) 1 (* n (fact (- n 1)))))
+            (defn answer () (double (+ 1 (if (< 1 2) 20 (fact 3)))))
+            (defn twice (f x) (f (f x)))
+            (defn quad (x) (twice double x))
+        "#;
+        let module = parse(None, source).unwrap();
+        let namespaces = load(Namespace::new(module).unwrap(), &HashMap::new()).unwrap();
+        for opt_level in [OptLevel::O0, OptLevel::O1, OptLevel::O2] {
+            let options = Options {
+                opt_level,
+                ..Options::default()
+            };
+            let bytes = codegen(&namespaces, &options).unwrap().bytes;
+            let (mut store, instance) = instantiate_bytes(&bytes);
+            assert_eq!(42.0, call(&mut store, &instance, "answer", ()));
+            assert_eq!(120.0, call(&mut store, &instance, "fact", 5.0));
+            assert_eq!(12.0, call(&mut store, &instance, "quad", 3.0));
+
+            let calls = calls(&bytes, "user/answer");
+            match opt_level {
+                OptLevel::O0 => assert_eq!(vec!["user/fact", "user/double"], calls),
+                OptLevel::O1 => assert_eq!(vec!["user/double"], calls),
+                OptLevel::O2 => assert!(calls.is_empty()),
+            }
+        }
+    }
+
+    #[test]
+    fn compile_to_typed_ir() {
+        let source = "(defn add (x y)\n  (let (z (+ x y)) z))\n(defn xs () (list 1))";
+        let output = compile_project("user", &sources(source), &Options::default()).unwrap();
+
+        let text = output.ir.to_string();
+        assert!(text.contains(
+            "(func $user/add (param $0 x any) (param $1 y any) (local $2 z number) (result number)\n  (let $2 (+ $0 $1)\n    $2))"
+        ));
+        assert!(text.contains("(func $user/xs (result list)\n  (call $runtime/cons 1 0))"));
+
+        let add = output
+            .ir
+            .functions
+            .iter()
+            .find(|definition| definition.name == "user/add")
+            .and_then(|definition| definition.function.as_ref())
+            .unwrap();
+        assert_eq!(ir::Type::Number, add.body.ty);
+        assert_eq!(Some(Region::new(2, 3, 2, 21)), add.body.region);
+    }
+
+    /// Returns the names of the functions called by the function with
+    /// the given name.
+    fn calls<'a>(bytes: &'a [u8], name: &str) -> Vec<&'a str> {
+        let (functions, _) = names(bytes);
+        let name_of = |idx: u32| functions.iter().find(|(i, _)| *i == idx).unwrap().1;
+        let mut imports = 0;
+        let mut idx = 0;
+        for payload in wasmparser::Parser::new(0).parse_all(bytes) {
+            match payload.unwrap() {
+                wasmparser::Payload::ImportSection(reader) => imports = reader.count(),
+                wasmparser::Payload::CodeSectionEntry(body) => {
+                    if name_of(imports + idx) == name {
+                        let mut calls = vec![];
+                        let mut reader = body.get_operators_reader().unwrap();
+                        while !reader.eof() {
+                            if let wasmparser::Operator::Call { function_index } =
+                                reader.read().unwrap()
+                            {
+                                calls.push(name_of(function_index));
+                            }
+                        }
+                        return calls;
+                    }
+                    idx += 1;
+                }
+                _ => {}
+            }
+        }
+        panic!("{} is not defined", name)
+    }
+
+    /// Function names and local names by function index.
+    type Names<'a> = (Vec<(u32, &'a str)>, Vec<(u32, Vec<&'a str>)>);
+
+    /// Returns the names from the `name` section of the given module.
+    fn names(bytes: &[u8]) -> Names<'_> {
+        let mut functions = vec![];
+        let mut locals = vec![];
+        for payload in wasmparser::Parser::new(0).parse_all(bytes) {
+            let wasmparser::Payload::CustomSection(reader) = payload.unwrap() else {
+                continue;
+            };
+            if reader.name() != "name" {
+                continue;
+            }
+            let names = wasmparser::NameSectionReader::new(reader.data(), reader.data_offset());
+            for name in names {
+                match name.unwrap() {
+                    wasmparser::Name::Function(map) => {
+                        for naming in map {
+                            let naming = naming.unwrap();
+                            functions.push((naming.index, naming.name));
+                        }
+                    }
+                    wasmparser::Name::Local(map) => {
+                        for function in map {
+                            let function = function.unwrap();
+                            let names = function.names.into_iter();
+                            let names: Vec<&str> =
+                                names.map(|naming| naming.unwrap().name).collect();
+                            locals.push((function.index, names));
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+        (functions, locals)
+    }
+
+    fn sources(source: &str) -> HashMap<String, String> {
+        [("user".to_string(), source.to_string())].into()
+    }
+
+    fn instantiate(source: &str) -> (Store<()>, Instance) {
+        instantiate_bytes(&compile(None, source).unwrap())
+    }
+
+    fn instantiate_bytes(bytes: &[u8]) -> (Store<()>, Instance) {
+        let engine = Engine::default();
+        let module = Module::new(&engine, bytes).unwrap();
+        let mut store = Store::new(&engine, ());
+        let instance = Linker::new(&engine)
+            .instantiate(&mut store, &module)
+            .unwrap()
+            .start(&mut store)
+            .unwrap();
+        (store, instance)
+    }
+
+    fn call<P: WasmParams>(
+        store: &mut Store<()>,
+        instance: &Instance,
+        name: &str,
+        params: P,
+    ) -> f64 {
+        instance
+            .get_typed_func::<P, f64>(&*store, name)
+            .unwrap()
+            .call(store, params)
+            .unwrap()
     }
 }
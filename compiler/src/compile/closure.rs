@@ -0,0 +1,96 @@
+use crate::parse::Expr;
+
+/// Returns the names of all symbols used in the body of a `fn` with the
+/// given parameters, that are not bound within it, in the order of
+/// their first occurrence.
+///
+/// Which of them have to be captured depends on what is in scope where
+/// the `fn` is defined, so this includes references to functions or
+/// builtins as well.
+pub fn free_variables(params: &[Expr], body: &Expr) -> Vec<String> {
+    let mut bound = vec![];
+    let mut free = vec![];
+    bind_all(params, &mut bound);
+    collect(body, &mut bound, &mut free);
+    free
+}
+
+fn collect<'a>(expr: &'a Expr, bound: &mut Vec<&'a str>, free: &mut Vec<String>) {
+    match expr {
+        Expr::Number { .. } | Expr::Boolean { .. } => {}
+        Expr::Symbol {
+            namespace, value, ..
+        } => {
+            if namespace.is_empty() && !bound.contains(&value.as_str()) && !free.contains(value) {
+                free.push(value.clone());
+            }
+        }
+        Expr::List { expressions, .. } => match expressions.as_slice() {
+            [Expr::Symbol { value, .. }, Expr::List {
+                expressions: params,
+                ..
+            }, body]
+                if value == "fn" =>
+            {
+                let scope = bound.len();
+                bind_all(params, bound);
+                collect(body, bound, free);
+                bound.truncate(scope);
+            }
+            [Expr::Symbol { value, .. }, Expr::List {
+                expressions: bindings,
+                ..
+            }, body]
+                if value == "let" =>
+            {
+                let scope = bound.len();
+                for binding in bindings.chunks(2) {
+                    if let [name, value] = binding {
+                        collect(value, bound, free);
+                        bind_all(std::slice::from_ref(name), bound);
+                    }
+                }
+                collect(body, bound, free);
+                bound.truncate(scope);
+            }
+            expressions => {
+                for expr in expressions {
+                    collect(expr, bound, free);
+                }
+            }
+        },
+        Expr::Vector { expressions, .. } => {
+            for expr in expressions {
+                collect(expr, bound, free);
+            }
+        }
+    }
+}
+
+fn bind_all<'a>(names: &'a [Expr], bound: &mut Vec<&'a str>) {
+    for name in names {
+        if let Expr::Symbol { value, .. } = name {
+            bound.push(value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::compile::closure::free_variables;
+    use crate::parse::{parse, Expr};
+
+    #[test]
+    fn free_variables_skip_bound_symbols() {
+        let module = parse(None, "(fn (x) (let (y (+ x z)) (fn (w) (* w y n))))").unwrap();
+        let (params, body) = match module.expressions.as_slice() {
+            [Expr::List { expressions, .. }] => match expressions.as_slice() {
+                [_, Expr::List { expressions, .. }, body] => (expressions, body),
+                _ => unreachable!(),
+            },
+            _ => unreachable!(),
+        };
+
+        assert_eq!(vec!["+", "z", "*", "n"], free_variables(params, body));
+    }
+}
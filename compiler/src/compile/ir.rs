@@ -0,0 +1,573 @@
+//! The intermediate representation functions are lowered to, before
+//! they are optimized and compiled to WebAssembly.
+//!
+//! Symbols are resolved to locals or function indices and special forms
+//! are desugared, so neither the optimizer nor the code generator needs
+//! to know about scopes or namespaces.
+//!
+//! The IR of a whole module can be dumped as JSON or in a textual form
+//! for debugging, see [`Program`].
+
+use crate::reporting::Region;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fmt;
+
+/// The functions of a compiled module, before they are compiled to
+/// WebAssembly.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Program {
+    /// All functions, which are not closures, by their function index.
+    pub functions: Vec<Definition>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Definition {
+    pub index: u32,
+    pub name: String,
+    /// The body, if the function is defined in source code, i.e. it is
+    /// no import or builtin.
+    pub function: Option<Function>,
+}
+
+/// The body of a function together with its locals.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Function {
+    /// Number of parameters, which are the first locals.
+    pub params: u32,
+    /// All locals by their index.
+    pub locals: Vec<Local>,
+    pub body: Expr,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Local {
+    /// The name in the source code, which is empty for locals without
+    /// one.
+    pub name: String,
+    #[serde(rename = "type")]
+    pub ty: Type,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Expr {
+    /// The region of the source code it is lowered from, if any.
+    pub region: Option<Region>,
+    /// What the expression evaluates to, as far as it is known.
+    #[serde(rename = "type")]
+    pub ty: Type,
+    pub kind: Kind,
+}
+
+/// The type of a value.
+///
+/// Every value is represented as an `f64` at runtime, except for
+/// values known to be booleans, which are `i32`s until they are passed
+/// to a function, stored or returned. Types are only tracked as far as
+/// they are obvious.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Type {
+    Any,
+    Number,
+    Boolean,
+    List,
+    Function { arity: usize },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Kind {
+    Number(f64),
+    Boolean(bool),
+    Local(u32),
+    /// The function at index `function` used as a value, which is
+    /// wrapped in a closure.
+    Reference {
+        function: u32,
+        arity: usize,
+    },
+    Binary {
+        op: Op,
+        left: Box<Expr>,
+        right: Box<Expr>,
+    },
+    Unary {
+        op: Unary,
+        value: Box<Expr>,
+    },
+    /// Evaluates to `then`, if `cond` is neither `false` nor `0`, and
+    /// to `otherwise` otherwise.
+    If {
+        cond: Box<Expr>,
+        then: Box<Expr>,
+        otherwise: Box<Expr>,
+    },
+    /// Evaluates to `true`, if the value is `false` or `0`, and to
+    /// `false` otherwise.
+    Not(Box<Expr>),
+    /// Evaluates `body` with `value` assigned to `local`.
+    Let {
+        local: u32,
+        value: Box<Expr>,
+        body: Box<Expr>,
+    },
+    /// Evaluates every expression in order to the value of the last
+    /// one.
+    Do(Vec<Expr>),
+    Call {
+        function: u32,
+        args: Vec<Expr>,
+    },
+    /// Calls the closure `callee` evaluates to.
+    CallClosure {
+        callee: Box<Expr>,
+        args: Vec<Expr>,
+    },
+    /// A closure capturing the given locals.
+    ///
+    /// The first parameter of `function` is the closure itself,
+    /// followed by the actual parameters. The captured values are
+    /// loaded into the locals right after the parameters.
+    Closure {
+        function: Box<Function>,
+        captured: Vec<u32>,
+    },
+    /// An assertion of a test, which fails if `failed` is not `0`.
+    Assert(Box<Expr>),
+    /// A symbol, which does not refer to anything, or a call of one.
+    ///
+    /// Name resolution reports these before anything is lowered, so no
+    /// code is generated for it.
+    Unbound,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Op {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Eq,
+    Ne,
+    Min,
+    Max,
+    /// The bitwise operators truncate their operands to 64-bit integers.
+    BitAnd,
+    BitOr,
+    BitXor,
+    ShiftLeft,
+    /// Shifts right, preserving the sign.
+    ShiftRight,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Unary {
+    Neg,
+    Abs,
+    Sqrt,
+    Floor,
+    Ceil,
+    /// Rounds towards zero.
+    Trunc,
+    /// Truncates its operand to a 64-bit integer and flips every bit.
+    BitNot,
+}
+
+impl Expr {
+    /// Returns an expression of the type [`Type::of`] its kind.
+    pub fn new(region: Region, kind: Kind) -> Self {
+        Expr {
+            region: Some(region),
+            ty: Type::of(&kind),
+            kind,
+        }
+    }
+
+    /// Returns an expression, which is not part of the source code.
+    pub fn generated(kind: Kind) -> Self {
+        Expr {
+            region: None,
+            ty: Type::of(&kind),
+            kind,
+        }
+    }
+
+    pub fn with_type(self, ty: Type) -> Self {
+        Expr { ty, ..self }
+    }
+
+    /// Returns the immediate subexpressions of this expression, which
+    /// excludes the bodies of closures.
+    pub fn children(&self) -> Vec<&Expr> {
+        match &self.kind {
+            Kind::Number(_)
+            | Kind::Boolean(_)
+            | Kind::Local(_)
+            | Kind::Reference { .. }
+            | Kind::Closure { .. }
+            | Kind::Unbound => vec![],
+            Kind::Binary { left, right, .. } => vec![left, right],
+            Kind::If {
+                cond,
+                then,
+                otherwise,
+            } => vec![cond, then, otherwise],
+            Kind::Not(value) | Kind::Unary { value, .. } => vec![value],
+            Kind::Let { value, body, .. } => vec![value, body],
+            Kind::Do(exprs) | Kind::Call { args: exprs, .. } => exprs.iter().collect(),
+            Kind::CallClosure { callee, args } => {
+                std::iter::once(&**callee).chain(args.iter()).collect()
+            }
+            Kind::Assert(failed) => vec![failed],
+        }
+    }
+
+    /// See [`Expr::children`].
+    pub fn children_mut(&mut self) -> Vec<&mut Expr> {
+        match &mut self.kind {
+            Kind::Number(_)
+            | Kind::Boolean(_)
+            | Kind::Local(_)
+            | Kind::Reference { .. }
+            | Kind::Closure { .. }
+            | Kind::Unbound => vec![],
+            Kind::Binary { left, right, .. } => vec![left, right],
+            Kind::If {
+                cond,
+                then,
+                otherwise,
+            } => vec![cond, then, otherwise],
+            Kind::Not(value) | Kind::Unary { value, .. } => vec![value],
+            Kind::Let { value, body, .. } => vec![value, body],
+            Kind::Do(exprs) | Kind::Call { args: exprs, .. } => exprs.iter_mut().collect(),
+            Kind::CallClosure { callee, args } => std::iter::once(&mut **callee)
+                .chain(args.iter_mut())
+                .collect(),
+            Kind::Assert(failed) => vec![failed],
+        }
+    }
+
+    /// Returns whether `f` holds for this expression or any expression
+    /// nested in it, including the bodies of closures.
+    pub fn any(&self, f: &dyn Fn(&Expr) -> bool) -> bool {
+        f(self)
+            || match &self.kind {
+                Kind::Closure { function, .. } => function.body.any(f),
+                _ => self.children().into_iter().any(|expr| expr.any(f)),
+            }
+    }
+
+    /// Returns the number of expressions this expression consists of,
+    /// including the bodies of closures.
+    pub fn size(&self) -> usize {
+        match &self.kind {
+            Kind::Closure { function, .. } => 1 + function.body.size(),
+            _ => 1 + self.children().into_iter().map(Expr::size).sum::<usize>(),
+        }
+    }
+}
+
+impl Type {
+    /// Returns the type of an expression of the given kind, which is
+    /// [`Type::Any`] for locals and calls, as it depends on context.
+    pub fn of(kind: &Kind) -> Type {
+        match kind {
+            Kind::Number(_) | Kind::Unary { .. } | Kind::Assert(_) => Type::Number,
+            Kind::Binary { op, .. } if op.is_comparison() => Type::Boolean,
+            Kind::Binary { .. } => Type::Number,
+            Kind::Boolean(_) | Kind::Not(_) => Type::Boolean,
+            Kind::Local(_) | Kind::Call { .. } | Kind::CallClosure { .. } | Kind::Unbound => {
+                Type::Any
+            }
+            Kind::Reference { arity, .. } => Type::Function { arity: *arity },
+            Kind::Closure { function, .. } => Type::Function {
+                arity: function.params as usize - 1,
+            },
+            Kind::If {
+                then, otherwise, ..
+            } => then.ty.join(otherwise.ty),
+            Kind::Let { body, .. } => body.ty,
+            Kind::Do(exprs) => exprs.last().map_or(Type::Number, |expr| expr.ty),
+        }
+    }
+
+    /// Returns the type of a value, which is either of both types.
+    pub fn join(self, other: Type) -> Type {
+        if self == other {
+            self
+        } else {
+            Type::Any
+        }
+    }
+}
+
+impl Op {
+    /// Returns whether this operator compares its operands, evaluating
+    /// to a boolean.
+    pub fn is_comparison(self) -> bool {
+        matches!(self, Op::Lt | Op::Le | Op::Gt | Op::Ge | Op::Eq | Op::Ne)
+    }
+
+    /// Returns whether this operator works on integers rather than
+    /// floats.
+    pub fn is_bitwise(self) -> bool {
+        matches!(
+            self,
+            Op::BitAnd | Op::BitOr | Op::BitXor | Op::ShiftLeft | Op::ShiftRight
+        )
+    }
+
+    pub fn symbol(self) -> &'static str {
+        match self {
+            Op::Add => "+",
+            Op::Sub => "-",
+            Op::Mul => "*",
+            Op::Div => "/",
+            Op::Lt => "<",
+            Op::Le => "<=",
+            Op::Gt => ">",
+            Op::Ge => ">=",
+            Op::Eq => "=",
+            Op::Ne => "not=",
+            Op::Min => "min",
+            Op::Max => "max",
+            Op::BitAnd => "bit-and",
+            Op::BitOr => "bit-or",
+            Op::BitXor => "bit-xor",
+            Op::ShiftLeft => "bit-shift-left",
+            Op::ShiftRight => "bit-shift-right",
+        }
+    }
+
+    /// Returns the constant this operator evaluates to for the given
+    /// operands.
+    pub fn apply(self, left: f64, right: f64) -> Kind {
+        match self {
+            Op::Add => Kind::Number(left + right),
+            Op::Sub => Kind::Number(left - right),
+            Op::Mul => Kind::Number(left * right),
+            Op::Div => Kind::Number(left / right),
+            Op::Lt => Kind::Boolean(left < right),
+            Op::Le => Kind::Boolean(left <= right),
+            Op::Gt => Kind::Boolean(left > right),
+            Op::Ge => Kind::Boolean(left >= right),
+            Op::Eq => Kind::Boolean(left == right),
+            Op::Ne => Kind::Boolean(left != right),
+            // Unlike `f64::min` and `f64::max`, WebAssembly propagates
+            // `NaN`.
+            Op::Min | Op::Max if left.is_nan() || right.is_nan() => Kind::Number(f64::NAN),
+            Op::Min => Kind::Number(left.min(right)),
+            Op::Max => Kind::Number(left.max(right)),
+            // Casts saturate like the truncations of WebAssembly and
+            // shifts wrap the same way.
+            Op::BitAnd => Kind::Number(((left as i64) & (right as i64)) as f64),
+            Op::BitOr => Kind::Number(((left as i64) | (right as i64)) as f64),
+            Op::BitXor => Kind::Number(((left as i64) ^ (right as i64)) as f64),
+            Op::ShiftLeft => Kind::Number((left as i64).wrapping_shl(right as i64 as u32) as f64),
+            Op::ShiftRight => Kind::Number((left as i64).wrapping_shr(right as i64 as u32) as f64),
+        }
+    }
+}
+
+impl Unary {
+    pub fn symbol(self) -> &'static str {
+        match self {
+            Unary::Neg => "neg",
+            Unary::Abs => "abs",
+            Unary::Sqrt => "sqrt",
+            Unary::Floor => "floor",
+            Unary::Ceil => "ceil",
+            Unary::Trunc => "trunc",
+            Unary::BitNot => "bit-not",
+        }
+    }
+
+    /// Returns the constant this operator evaluates to for the given
+    /// operand.
+    pub fn apply(self, value: f64) -> f64 {
+        match self {
+            Unary::Neg => -value,
+            Unary::Abs => value.abs(),
+            Unary::Sqrt => value.sqrt(),
+            Unary::Floor => value.floor(),
+            Unary::Ceil => value.ceil(),
+            Unary::Trunc => value.trunc(),
+            Unary::BitNot => !(value as i64) as f64,
+        }
+    }
+}
+
+/// The textual form of a program, where every function is printed as
+///
+/// ```text
+/// (func $user/add (param $0 x any) (param $1 y any) (local $2 z number)
+///   (let $2 (+ $0 $1)
+///     $2))
+/// ```
+impl fmt::Display for Program {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let names: HashMap<u32, &str> = self
+            .functions
+            .iter()
+            .map(|definition| (definition.index, definition.name.as_str()))
+            .collect();
+        let printer = Printer { names: &names };
+        for definition in &self.functions {
+            if let Some(function) = &definition.function {
+                let mut out = String::new();
+                printer.function(&mut out, &definition.name, function, 0);
+                writeln!(f, "{}", out)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Type::Any => write!(f, "any"),
+            Type::Number => write!(f, "number"),
+            Type::Boolean => write!(f, "boolean"),
+            Type::List => write!(f, "list"),
+            Type::Function { arity } => write!(f, "fn/{}", arity),
+        }
+    }
+}
+
+/// Prints expressions as S-expressions, breaking lines inside of the
+/// forms, which introduce new blocks.
+struct Printer<'a> {
+    /// Names of the functions by index.
+    names: &'a HashMap<u32, &'a str>,
+}
+
+impl Printer<'_> {
+    fn function(&self, out: &mut String, name: &str, function: &Function, indent: usize) {
+        out.push_str(&format!("(func ${}", name));
+        for (idx, local) in function.locals.iter().enumerate() {
+            let kind = if (idx as u32) < function.params {
+                "param"
+            } else {
+                "local"
+            };
+            out.push_str(&format!(" ({} ${}", kind, idx));
+            if !local.name.is_empty() {
+                out.push_str(&format!(" {}", local.name));
+            }
+            out.push_str(&format!(" {})", local.ty));
+        }
+        out.push_str(&format!(" (result {})", function.body.ty));
+        self.newline(out, indent + 1);
+        self.expr(out, &function.body, indent + 1);
+        out.push(')');
+    }
+
+    fn expr(&self, out: &mut String, expr: &Expr, indent: usize) {
+        match &expr.kind {
+            Kind::Number(value) => out.push_str(&value.to_string()),
+            Kind::Boolean(value) => out.push_str(&value.to_string()),
+            Kind::Local(idx) => out.push_str(&format!("${}", idx)),
+            Kind::Reference { function, .. } => {
+                out.push_str(&format!("(ref {})", self.name(*function)));
+            }
+            Kind::Binary { op, left, right } => {
+                out.push_str(&format!("({} ", op.symbol()));
+                self.expr(out, left, indent);
+                out.push(' ');
+                self.expr(out, right, indent);
+                out.push(')');
+            }
+            Kind::If {
+                cond,
+                then,
+                otherwise,
+            } => {
+                out.push_str("(if ");
+                self.expr(out, cond, indent);
+                for branch in [then, otherwise] {
+                    self.newline(out, indent + 1);
+                    self.expr(out, branch, indent + 1);
+                }
+                out.push(')');
+            }
+            Kind::Unary { op, value } => {
+                out.push_str(&format!("({} ", op.symbol()));
+                self.expr(out, value, indent);
+                out.push(')');
+            }
+            Kind::Not(value) => {
+                out.push_str("(not ");
+                self.expr(out, value, indent);
+                out.push(')');
+            }
+            Kind::Let { local, value, body } => {
+                out.push_str(&format!("(let ${} ", local));
+                self.expr(out, value, indent);
+                self.newline(out, indent + 1);
+                self.expr(out, body, indent + 1);
+                out.push(')');
+            }
+            Kind::Do(exprs) => {
+                out.push_str("(do");
+                for expr in exprs {
+                    self.newline(out, indent + 1);
+                    self.expr(out, expr, indent + 1);
+                }
+                out.push(')');
+            }
+            Kind::Call { function, args } => {
+                out.push_str(&format!("(call {}", self.name(*function)));
+                self.args(out, args, indent);
+            }
+            Kind::CallClosure { callee, args } => {
+                out.push_str("(call_closure ");
+                self.expr(out, callee, indent);
+                self.args(out, args, indent);
+            }
+            Kind::Closure { function, captured } => {
+                out.push_str("(closure (");
+                let captured: Vec<String> =
+                    captured.iter().map(|idx| format!("${}", idx)).collect();
+                out.push_str(&captured.join(" "));
+                out.push(')');
+                self.newline(out, indent + 1);
+                self.function(out, "fn", function, indent + 1);
+                out.push(')');
+            }
+            Kind::Assert(failed) => {
+                out.push_str("(assert ");
+                self.expr(out, failed, indent);
+                out.push(')');
+            }
+            Kind::Unbound => out.push_str("unbound"),
+        }
+    }
+
+    fn args(&self, out: &mut String, args: &[Expr], indent: usize) {
+        for arg in args {
+            out.push(' ');
+            self.expr(out, arg, indent);
+        }
+        out.push(')');
+    }
+
+    fn name(&self, function: u32) -> String {
+        match self.names.get(&function) {
+            Some(name) => format!("${}", name),
+            None => function.to_string(),
+        }
+    }
+
+    fn newline(&self, out: &mut String, indent: usize) {
+        out.push('\n');
+        out.push_str(&"  ".repeat(indent));
+    }
+}
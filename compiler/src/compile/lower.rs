@@ -0,0 +1,629 @@
+//! Lowering of function bodies to the intermediate representation.
+
+use crate::compile::closure::free_variables;
+use crate::compile::ir::{Expr, Function, Kind, Local, Op, Type, Unary};
+use crate::compile::runtime::Builtin;
+use crate::compile::{qualified, Defn, CORE_NAMESPACE};
+use crate::parse;
+use crate::reporting::Region;
+use std::collections::HashMap;
+
+/// What is known about the module, while lowering a function.
+#[derive(Clone, Copy)]
+pub struct Env<'a> {
+    /// The namespace the function is defined in.
+    pub namespace: &'a str,
+    /// The namespaces usable as qualifiers, see
+    /// [`Namespace::aliases`](crate::project::Namespace::aliases).
+    pub aliases: &'a HashMap<String, String>,
+    pub defns: &'a HashMap<String, Defn>,
+    /// Index of the first builtin.
+    pub builtins: u32,
+}
+
+/// Lowers the function with the given parameters and body.
+pub fn function(env: Env, params: &[parse::Expr], body: &parse::Expr) -> Function {
+    let mut lowering = Lowering::new(env);
+    for param in params {
+        lowering.bind(param);
+    }
+    let body = lowering.lower(body);
+    lowering.function(body)
+}
+
+/// Lowers the given expressions to a function without parameters,
+/// which evaluates them in order after `prefix`.
+pub fn sequence(env: Env, prefix: Vec<Expr>, exprs: &[&parse::Expr]) -> Function {
+    let mut lowering = Lowering::new(env);
+    let mut body = prefix;
+    for expr in exprs {
+        body.push(lowering.lower(expr));
+    }
+    lowering.function(Expr::generated(Kind::Do(body)))
+}
+
+/// The state of lowering the body of a function.
+struct Lowering<'a> {
+    env: Env<'a>,
+    /// Names of the locals in scope with their index, innermost last.
+    scope: Vec<(String, u32)>,
+    /// All locals, see [`Function::locals`].
+    locals: Vec<Local>,
+    params: u32,
+}
+
+impl<'a> Lowering<'a> {
+    fn new(env: Env<'a>) -> Self {
+        Lowering {
+            env,
+            scope: vec![],
+            locals: vec![],
+            params: 0,
+        }
+    }
+
+    /// Adds a parameter for the given symbol.
+    fn bind(&mut self, param: &parse::Expr) {
+        match param {
+            parse::Expr::Symbol { value, .. } => {
+                self.define(value, Type::Any);
+            }
+            _ => {
+                self.locals.push(Local {
+                    name: String::new(),
+                    ty: Type::Any,
+                });
+            }
+        }
+        self.params += 1;
+    }
+
+    /// Returns the index of a new local of the given type, which is
+    /// never in scope.
+    fn temporary(&mut self, ty: Type) -> u32 {
+        let idx = self.locals.len() as u32;
+        self.locals.push(Local {
+            name: String::new(),
+            ty,
+        });
+        idx
+    }
+
+    /// Returns the index of a new local of the given type, which is in
+    /// scope as `name`.
+    fn define(&mut self, name: &str, ty: Type) -> u32 {
+        let idx = self.locals.len() as u32;
+        self.locals.push(Local {
+            name: name.to_string(),
+            ty,
+        });
+        self.scope.push((name.to_string(), idx));
+        idx
+    }
+
+    fn local(&self, name: &str) -> Option<u32> {
+        self.scope
+            .iter()
+            .rev()
+            .find(|(local, _)| local == name)
+            .map(|(_, idx)| *idx)
+    }
+
+    /// Returns the function the given symbol refers to.
+    ///
+    /// Unqualified symbols are looked up in the current namespace
+    /// first and in `core` afterwards, qualified symbols in the
+    /// namespace their qualifier is an alias for.
+    fn defn(&self, namespace: &[String], name: &str) -> Option<&'a Defn> {
+        let defns = self.env.defns;
+        if namespace.is_empty() {
+            defns
+                .get(&qualified(self.env.namespace, name))
+                .or_else(|| defns.get(&qualified(CORE_NAMESPACE, name)))
+        } else {
+            let namespace = namespace.join(".");
+            let namespace = self.env.aliases.get(&namespace).unwrap_or(&namespace);
+            defns.get(&qualified(namespace, name))
+        }
+    }
+
+    /// Returns the function index of the given builtin.
+    fn builtin(&self, builtin: Builtin) -> u32 {
+        builtin.index(self.env.builtins)
+    }
+
+    fn function(self, body: Expr) -> Function {
+        Function {
+            params: self.params,
+            locals: self.locals,
+            body,
+        }
+    }
+
+    fn lower(&mut self, expr: &parse::Expr) -> Expr {
+        let region = *expr.region();
+        let kind = match expr {
+            parse::Expr::List { expressions, .. } => match expressions.as_slice() {
+                [parse::Expr::Symbol {
+                    namespace, value, ..
+                }, args @ ..]
+                    if namespace.is_empty() && self.local(value).is_none() =>
+                {
+                    self.lower_form(value, args)
+                }
+                [parse::Expr::Symbol {
+                    namespace, value, ..
+                }, args @ ..]
+                    if !namespace.is_empty() =>
+                {
+                    match self.defn(namespace, value) {
+                        Some(defn) => self.lower_call(defn.idx, args),
+                        None => Kind::Unbound,
+                    }
+                }
+                [callee, args @ ..] => Kind::CallClosure {
+                    callee: Box::new(self.lower(callee)),
+                    args: self.lower_all(args),
+                },
+                // The empty list.
+                [] => return Expr::new(region, Kind::Number(0.0)).with_type(Type::List),
+            },
+            parse::Expr::Number { value, .. } => Kind::Number(*value),
+            parse::Expr::Boolean { value, .. } => Kind::Boolean(*value),
+            parse::Expr::Vector { expressions, .. } => self.lower_list(expressions),
+            parse::Expr::Symbol {
+                namespace, value, ..
+            } => {
+                let local = namespace.is_empty().then(|| self.local(value)).flatten();
+                let builtin = namespace
+                    .is_empty()
+                    .then(|| Builtin::from_name(value))
+                    .flatten();
+                if let Some(idx) = local {
+                    Kind::Local(idx)
+                } else if let Some(builtin) = builtin {
+                    Kind::Reference {
+                        function: self.builtin(builtin),
+                        arity: builtin.params().len(),
+                    }
+                } else if let Some(defn) = self.defn(namespace, value) {
+                    Kind::Reference {
+                        function: defn.idx,
+                        arity: defn.arity,
+                    }
+                } else {
+                    Kind::Unbound
+                }
+            }
+        };
+        let ty = match &kind {
+            Kind::Local(idx) => self.locals[*idx as usize].ty,
+            Kind::Call { function, .. } => self.result_type(*function),
+            kind => Type::of(kind),
+        };
+        Expr::new(region, kind).with_type(ty)
+    }
+
+    /// Returns the type of the values the function at index `function`
+    /// returns, which is only known for builtins.
+    fn result_type(&self, function: u32) -> Type {
+        let builtin = function
+            .checked_sub(self.env.builtins)
+            .and_then(|idx| Builtin::ALL.get(idx as usize));
+        match builtin {
+            Some(Builtin::Cons | Builtin::Rest) => Type::List,
+            Some(Builtin::Alloc | Builtin::Count | Builtin::IsEmpty) => Type::Number,
+            Some(Builtin::First | Builtin::Nth | Builtin::Apply) | None => Type::Any,
+        }
+    }
+
+    fn lower_all(&mut self, exprs: &[parse::Expr]) -> Vec<Expr> {
+        exprs.iter().map(|expr| self.lower(expr)).collect()
+    }
+
+    /// Lowers a special form or a call of a named function.
+    fn lower_form(&mut self, symbol: &str, args: &[parse::Expr]) -> Kind {
+        match symbol {
+            "+" => self.lower_binary(Op::Add, args),
+            "-" => self.lower_binary(Op::Sub, args),
+            "*" => self.lower_binary(Op::Mul, args),
+            "/" => self.lower_binary(Op::Div, args),
+            "<" => self.lower_comparison(Op::Lt, args),
+            "<=" => self.lower_comparison(Op::Le, args),
+            ">" => self.lower_comparison(Op::Gt, args),
+            ">=" => self.lower_comparison(Op::Ge, args),
+            "=" => self.lower_comparison(Op::Eq, args),
+            "not=" => match args {
+                [_, _] => self.lower_binary(Op::Ne, args),
+                _ => Kind::Not(Box::new(Expr::generated(
+                    self.lower_comparison(Op::Eq, args),
+                ))),
+            },
+            "and" => self.lower_and(args),
+            "or" => self.lower_or(args),
+            "not" => self.lower_not(args),
+            "min" => self.lower_binary(Op::Min, args),
+            "max" => self.lower_binary(Op::Max, args),
+            "bit-and" => self.lower_binary(Op::BitAnd, args),
+            "bit-or" => self.lower_binary(Op::BitOr, args),
+            "bit-xor" => self.lower_binary(Op::BitXor, args),
+            "bit-shift-left" => self.lower_binary(Op::ShiftLeft, args),
+            "bit-shift-right" => self.lower_binary(Op::ShiftRight, args),
+            "inc" => self.lower_step(Op::Add, args),
+            "dec" => self.lower_step(Op::Sub, args),
+            "quot" => self.lower_quot(args),
+            "mod" => self.lower_remainder(Unary::Floor, args),
+            "rem" => self.lower_remainder(Unary::Trunc, args),
+            "neg" => self.lower_unary(Unary::Neg, args),
+            "abs" => self.lower_unary(Unary::Abs, args),
+            "sqrt" => self.lower_unary(Unary::Sqrt, args),
+            "floor" => self.lower_unary(Unary::Floor, args),
+            "ceil" => self.lower_unary(Unary::Ceil, args),
+            "bit-not" => self.lower_unary(Unary::BitNot, args),
+            "if" => self.lower_if(args),
+            "let" => self.lower_let(args),
+            "fn" => self.lower_fn(args),
+            "list" => self.lower_list(args),
+            "is" => self.lower_is(args),
+            "assert=" => self.lower_assert_eq(args),
+            _ => {
+                if let Some(builtin) = Builtin::from_name(symbol) {
+                    self.lower_call(self.builtin(builtin), args)
+                } else if let Some(defn) = self.defn(&[], symbol) {
+                    self.lower_call(defn.idx, args)
+                } else {
+                    Kind::Unbound
+                }
+            }
+        }
+    }
+
+    /// `(+ 3 5 6 7)` is lowered to `(+ (+ (+ 3 5) 6) 7)`.
+    ///
+    /// With a single argument, `(- x)` is lowered to `(neg x)`, `(/ x)`
+    /// to `(/ 1 x)` and every other operator to just `x`.
+    fn lower_binary(&mut self, op: Op, args: &[parse::Expr]) -> Kind {
+        match args {
+            [value] if op == Op::Sub => self.lower_unary(Unary::Neg, args),
+            [value] if op == Op::Div => Kind::Binary {
+                op,
+                left: Box::new(Expr::generated(Kind::Number(1.0))),
+                right: Box::new(self.lower(value)),
+            },
+            [head, rest @ ..] => {
+                let mut left = self.lower(head);
+                for expr in rest {
+                    let right = self.lower(expr);
+                    let region = left.region.zip(right.region).map(|(left, right)| Region {
+                        start: left.start,
+                        end: right.end,
+                    });
+                    let kind = Kind::Binary {
+                        op,
+                        left: Box::new(left),
+                        right: Box::new(right),
+                    };
+                    left = Expr {
+                        region,
+                        ty: Type::of(&kind),
+                        kind,
+                    };
+                }
+                left.kind
+            }
+            _ => unreachable!("arithmetic is checked during resolution"),
+        }
+    }
+
+    /// `(< a b c)` is lowered to `(let (x a y b) (and (< x y) (let (z c)
+    /// (< y z))))`, so it stops at the first comparison, which does not
+    /// hold. `(< a)` evaluates `a` and is `true`.
+    fn lower_comparison(&mut self, op: Op, args: &[parse::Expr]) -> Kind {
+        match args {
+            [value] => Kind::Do(vec![
+                self.lower(value),
+                Expr::generated(Kind::Boolean(true)),
+            ]),
+            [_, _] => self.lower_binary(op, args),
+            [first, rest @ ..] => {
+                let value = self.lower(first);
+                let local = self.temporary(value.ty);
+                let left = Expr::generated(Kind::Local(local)).with_type(value.ty);
+                Kind::Let {
+                    local,
+                    value: Box::new(value),
+                    body: Box::new(Expr::generated(self.lower_chain(op, left, rest))),
+                }
+            }
+            [] => unreachable!("{} is checked during resolution", op.symbol()),
+        }
+    }
+
+    /// Compares `left` with the first of `args`, and that with the
+    /// next one and so on, see [`Lowering::lower_comparison`].
+    fn lower_chain(&mut self, op: Op, left: Expr, args: &[parse::Expr]) -> Kind {
+        let binary = |left, right| Kind::Binary {
+            op,
+            left: Box::new(left),
+            right: Box::new(right),
+        };
+        match args {
+            [last] => binary(left, self.lower(last)),
+            [next, rest @ ..] => {
+                let value = self.lower(next);
+                let local = self.temporary(value.ty);
+                let right = || Expr::generated(Kind::Local(local)).with_type(value.ty);
+                let test = Kind::If {
+                    cond: Box::new(Expr::generated(binary(left, right()))),
+                    then: Box::new(Expr::generated(self.lower_chain(op, right(), rest))),
+                    otherwise: Box::new(Expr::generated(Kind::Boolean(false))),
+                };
+                Kind::Let {
+                    local,
+                    value: Box::new(value),
+                    body: Box::new(Expr::generated(test)),
+                }
+            }
+            [] => unreachable!("{} is checked during resolution", op.symbol()),
+        }
+    }
+
+    fn lower_unary(&mut self, op: Unary, args: &[parse::Expr]) -> Kind {
+        match args {
+            [value] => Kind::Unary {
+                op,
+                value: Box::new(self.lower(value)),
+            },
+            _ => unreachable!("{} is checked during resolution", op.symbol()),
+        }
+    }
+
+    /// `(inc x)` is lowered to `(+ x 1)` and `(dec x)` to `(- x 1)`.
+    fn lower_step(&mut self, op: Op, args: &[parse::Expr]) -> Kind {
+        match args {
+            [value] => Kind::Binary {
+                op,
+                left: Box::new(self.lower(value)),
+                right: Box::new(Expr::generated(Kind::Number(1.0))),
+            },
+            _ => unreachable!("{} is checked during resolution", op.symbol()),
+        }
+    }
+
+    /// `(quot a b)` is lowered to `(trunc (/ a b))`.
+    fn lower_quot(&mut self, args: &[parse::Expr]) -> Kind {
+        let quotient = match args {
+            [_, _] => self.lower_binary(Op::Div, args),
+            _ => unreachable!("quot is checked during resolution"),
+        };
+        Kind::Unary {
+            op: Unary::Trunc,
+            value: Box::new(Expr::generated(quotient)),
+        }
+    }
+
+    /// `(mod a b)` is lowered to `(let (x a y b) (- x (* y (floor (/ x
+    /// y)))))`, so its sign is the one of `b`, and `(rem a b)` the same
+    /// way with `trunc`, so its sign is the one of `a`.
+    fn lower_remainder(&mut self, round: Unary, args: &[parse::Expr]) -> Kind {
+        let (dividend, divisor) = match args {
+            [dividend, divisor] => (self.lower(dividend), self.lower(divisor)),
+            _ => unreachable!("mod and rem are checked during resolution"),
+        };
+        let x = self.temporary(Type::Number);
+        let y = self.temporary(Type::Number);
+        let local = |idx| Box::new(Expr::generated(Kind::Local(idx)).with_type(Type::Number));
+        let binary = |op, left, right| Box::new(Expr::generated(Kind::Binary { op, left, right }));
+        let rounded = Box::new(Expr::generated(Kind::Unary {
+            op: round,
+            value: binary(Op::Div, local(x), local(y)),
+        }));
+        let remainder = binary(Op::Sub, local(x), binary(Op::Mul, local(y), rounded));
+        let body = Kind::Let {
+            local: y,
+            value: Box::new(divisor),
+            body: remainder,
+        };
+        Kind::Let {
+            local: x,
+            value: Box::new(dividend),
+            body: Box::new(Expr::generated(body)),
+        }
+    }
+
+    /// `(and a b c)` is lowered to `(if a (if b c false) false)`, so it
+    /// stops at the first value, which is `false` or `0`. `(and)` is
+    /// `true`.
+    fn lower_and(&mut self, args: &[parse::Expr]) -> Kind {
+        match args {
+            [] => Kind::Boolean(true),
+            [value] => self.lower(value).kind,
+            [cond, rest @ ..] => Kind::If {
+                cond: Box::new(self.lower(cond)),
+                then: Box::new(Expr::generated(self.lower_and(rest))),
+                otherwise: Box::new(Expr::generated(Kind::Boolean(false))),
+            },
+        }
+    }
+
+    /// `(or a b)` is lowered to `(let (x a) (if x x b))`, so it stops at
+    /// the first value, which is neither `false` nor `0`, and evaluates
+    /// to it. `(or)` is `false`.
+    fn lower_or(&mut self, args: &[parse::Expr]) -> Kind {
+        match args {
+            [] => Kind::Boolean(false),
+            [value] => self.lower(value).kind,
+            [value, rest @ ..] => {
+                let value = self.lower(value);
+                let otherwise = Box::new(Expr::generated(self.lower_or(rest)));
+                // A boolean, which holds, is just `true`.
+                if value.ty == Type::Boolean {
+                    return Kind::If {
+                        cond: Box::new(value),
+                        then: Box::new(Expr::generated(Kind::Boolean(true))),
+                        otherwise,
+                    };
+                }
+                let local = self.temporary(value.ty);
+                let get = || Box::new(Expr::generated(Kind::Local(local)).with_type(value.ty));
+                let test = Kind::If {
+                    cond: get(),
+                    then: get(),
+                    otherwise,
+                };
+                Kind::Let {
+                    local,
+                    body: Box::new(Expr::generated(test)),
+                    value: Box::new(value),
+                }
+            }
+        }
+    }
+
+    /// `(not value)` is `true`, if `value` is `false` or `0`.
+    fn lower_not(&mut self, args: &[parse::Expr]) -> Kind {
+        match args {
+            [value] => Kind::Not(Box::new(self.lower(value))),
+            _ => unreachable!("not is checked during resolution"),
+        }
+    }
+
+    /// `(if cond then else)` evaluates `then`, if `cond` is neither
+    /// `false` nor `0`, and `else` otherwise. A missing `else` evaluates
+    /// to `0`.
+    fn lower_if(&mut self, args: &[parse::Expr]) -> Kind {
+        let (cond, then, otherwise) = match args {
+            [cond, then] => (cond, then, None),
+            [cond, then, otherwise] => (cond, then, Some(otherwise)),
+            _ => unreachable!("if is checked during resolution"),
+        };
+
+        Kind::If {
+            cond: Box::new(self.lower(cond)),
+            then: Box::new(self.lower(then)),
+            otherwise: Box::new(match otherwise {
+                Some(otherwise) => self.lower(otherwise),
+                None => Expr::generated(Kind::Number(0.0)),
+            }),
+        }
+    }
+
+    /// `(let (x 1 y (+ x 1)) body)` binds each value to a new local,
+    /// which is visible in the following bindings and the body.
+    fn lower_let(&mut self, args: &[parse::Expr]) -> Kind {
+        let (bindings, body) = match args {
+            [parse::Expr::List { expressions, .. }, body] => (expressions, body),
+            _ => unreachable!("let is checked during resolution"),
+        };
+
+        let scope = self.scope.len();
+        let mut values = vec![];
+        for binding in bindings.chunks(2) {
+            match binding {
+                [parse::Expr::Symbol { value: name, .. }, value] => {
+                    let value = self.lower(value);
+                    let local = self.define(name, value.ty);
+                    values.push((local, value));
+                }
+                _ => unreachable!("let is checked during resolution"),
+            }
+        }
+
+        let mut body = self.lower(body);
+        self.scope.truncate(scope);
+        for (local, value) in values.into_iter().rev() {
+            body = Expr {
+                region: body.region,
+                ty: body.ty,
+                kind: Kind::Let {
+                    local,
+                    value: Box::new(value),
+                    body: Box::new(body),
+                },
+            };
+        }
+        body.kind
+    }
+
+    /// `(fn (params...) body)` is lowered to a closure capturing the
+    /// locals in scope it uses.
+    fn lower_fn(&mut self, args: &[parse::Expr]) -> Kind {
+        let (params, body) = match args {
+            [parse::Expr::List { expressions, .. }, body] => (expressions, body),
+            _ => unreachable!("fn is checked during resolution"),
+        };
+
+        let captured: Vec<(String, u32)> = free_variables(params, body)
+            .into_iter()
+            .filter_map(|name| self.local(&name).map(|idx| (name, idx)))
+            .collect();
+
+        let mut lowering = Lowering::new(self.env);
+        lowering.locals.push(Local {
+            name: "env".to_string(),
+            ty: Type::Function {
+                arity: params.len(),
+            },
+        });
+        lowering.params += 1;
+        for param in params {
+            lowering.bind(param);
+        }
+        for (name, idx) in &captured {
+            lowering.define(name, self.locals[*idx as usize].ty);
+        }
+        let body = lowering.lower(body);
+
+        Kind::Closure {
+            function: Box::new(lowering.function(body)),
+            captured: captured.into_iter().map(|(_, idx)| idx).collect(),
+        }
+    }
+
+    /// `(list a b c)` is lowered to `(cons a (cons b (cons c ())))`.
+    fn lower_list(&mut self, args: &[parse::Expr]) -> Kind {
+        let mut list = Expr::generated(Kind::Number(0.0)).with_type(Type::List);
+        for head in self.lower_all(args).into_iter().rev() {
+            list = Expr {
+                region: head.region,
+                ty: Type::List,
+                kind: Kind::Call {
+                    function: self.builtin(Builtin::Cons),
+                    args: vec![head, list],
+                },
+            };
+        }
+        list.kind
+    }
+
+    /// `(is value)` fails, if `value` is `false` or `0`.
+    fn lower_is(&mut self, args: &[parse::Expr]) -> Kind {
+        let value = match args {
+            [value] => self.lower(value),
+            _ => unreachable!("is is checked during resolution"),
+        };
+        let failed = Kind::Not(Box::new(value));
+        Kind::Assert(Box::new(Expr::generated(failed)))
+    }
+
+    /// `(assert= expected actual)` fails, if both values are not equal.
+    fn lower_assert_eq(&mut self, args: &[parse::Expr]) -> Kind {
+        let (expected, actual) = match args {
+            [expected, actual] => (self.lower(expected), self.lower(actual)),
+            _ => unreachable!("assert= is checked during resolution"),
+        };
+        let failed = Kind::Binary {
+            op: Op::Ne,
+            left: Box::new(expected),
+            right: Box::new(actual),
+        };
+        Kind::Assert(Box::new(Expr::generated(failed)))
+    }
+
+    fn lower_call(&mut self, function: u32, args: &[parse::Expr]) -> Kind {
+        Kind::Call {
+            function,
+            args: self.lower_all(args),
+        }
+    }
+}
@@ -0,0 +1,273 @@
+//! Optimizations on the intermediate representation.
+
+use crate::compile::ir::{Expr, Function, Kind, Local, Type};
+use crate::reporting::Region;
+use std::collections::HashMap;
+
+/// Functions consisting of more expressions are never inlined.
+const INLINE_SIZE: usize = 16;
+
+/// How much to optimize a module.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum OptLevel {
+    /// No optimizations at all.
+    O0,
+    /// Folds constants and removes branches of `if`s with a constant
+    /// condition.
+    #[default]
+    O1,
+    /// Inlines small functions in addition to everything `O1` does.
+    O2,
+}
+
+pub struct Optimizer {
+    level: OptLevel,
+    /// Bodies of the functions, which may be inlined, by their function
+    /// index.
+    inlinable: HashMap<u32, Function>,
+}
+
+impl Optimizer {
+    /// Returns an optimizer for the given level, which considers the
+    /// given functions for inlining.
+    ///
+    /// A function is only inlined, if it is small, does not call
+    /// itself and contains no assertions, which behave differently
+    /// inside of tests.
+    pub fn new<'a>(level: OptLevel, functions: impl Iterator<Item = (u32, &'a Function)>) -> Self {
+        let inlinable = functions
+            .filter(|_| level >= OptLevel::O2)
+            .filter(|(idx, function)| {
+                function.body.size() <= INLINE_SIZE
+                    && !function.body.any(&|expr| match &expr.kind {
+                        Kind::Call { function, .. } => function == idx,
+                        Kind::Assert(_) | Kind::Unbound => true,
+                        _ => false,
+                    })
+            })
+            .map(|(idx, function)| (idx, function.clone()))
+            .collect();
+        Optimizer { level, inlinable }
+    }
+
+    pub fn optimize(&self, function: &mut Function) {
+        if self.level == OptLevel::O0 {
+            return;
+        }
+        self.inline(&mut function.body, &mut function.locals);
+        simplify(&mut function.body);
+    }
+
+    /// Replaces calls of inlinable functions with their bodies, binding
+    /// the arguments to new locals of the calling function.
+    ///
+    /// Inlined bodies are not inlined into any further, so mutually
+    /// recursive functions are expanded only once. The bodies of
+    /// closures are optimized on their own.
+    fn inline(&self, expr: &mut Expr, locals: &mut Vec<Local>) {
+        for child in expr.children_mut() {
+            self.inline(child, locals);
+        }
+        if let Kind::Closure { function, .. } = &mut expr.kind {
+            self.optimize(function);
+            return;
+        }
+
+        let Kind::Call { function, args } = &mut expr.kind else {
+            return;
+        };
+        let Some(callee) = self
+            .inlinable
+            .get(function)
+            .filter(|callee| callee.params as usize == args.len())
+        else {
+            return;
+        };
+
+        let offset = locals.len() as u32;
+        locals.extend(callee.locals.iter().cloned());
+        let mut body = callee.body.clone();
+        relocate(&mut body, offset, expr.region);
+        for (local, arg) in std::mem::take(args).into_iter().enumerate().rev() {
+            body = Expr {
+                region: expr.region,
+                ty: body.ty,
+                kind: Kind::Let {
+                    local: offset + local as u32,
+                    value: Box::new(arg),
+                    body: Box::new(body),
+                },
+            };
+        }
+        *expr = body;
+    }
+}
+
+/// Folds constants, propagates constant locals and removes branches,
+/// which are never taken.
+///
+/// Closures are simplified as part of [`Optimizer::inline`].
+fn simplify(expr: &mut Expr) {
+    match &mut expr.kind {
+        Kind::Let { local, value, body } => {
+            simplify(value);
+            if is_constant(&value.kind) {
+                substitute(body, *local, value);
+            }
+            simplify(body);
+        }
+        _ => {
+            for child in expr.children_mut() {
+                simplify(child);
+            }
+        }
+    }
+
+    let kind = std::mem::replace(&mut expr.kind, Kind::Unbound);
+    match kind {
+        Kind::Binary { op, left, right } => match (&left.kind, &right.kind) {
+            (Kind::Number(left), Kind::Number(right)) => {
+                expr.kind = op.apply(*left, *right);
+                expr.ty = Type::of(&expr.kind);
+            }
+            _ => expr.kind = Kind::Binary { op, left, right },
+        },
+        Kind::Unary { op, value } => match value.kind {
+            Kind::Number(number) => {
+                expr.kind = Kind::Number(op.apply(number));
+                expr.ty = Type::Number;
+            }
+            _ => expr.kind = Kind::Unary { op, value },
+        },
+        Kind::Not(value) => match truth(&value.kind) {
+            Some(truth) => {
+                expr.kind = Kind::Boolean(!truth);
+                expr.ty = Type::Boolean;
+            }
+            None => expr.kind = Kind::Not(value),
+        },
+        Kind::If {
+            cond,
+            then,
+            otherwise,
+        } => match truth(&cond.kind) {
+            Some(true) => *expr = *then,
+            Some(false) => *expr = *otherwise,
+            None => {
+                expr.kind = Kind::If {
+                    cond,
+                    then,
+                    otherwise,
+                }
+            }
+        },
+        // The value is constant, so dropping it has no effect.
+        Kind::Let { local, value, body } if is_constant(&value.kind) && !uses(&body, local) => {
+            *expr = *body;
+        }
+        kind => expr.kind = kind,
+    }
+}
+
+fn is_constant(kind: &Kind) -> bool {
+    matches!(kind, Kind::Number(_) | Kind::Boolean(_))
+}
+
+/// Returns whether a constant condition holds, i.e. it is neither
+/// `false` nor `0`.
+fn truth(kind: &Kind) -> Option<bool> {
+    match kind {
+        Kind::Number(number) => Some(*number != 0.0),
+        Kind::Boolean(value) => Some(*value),
+        _ => None,
+    }
+}
+
+/// Replaces every use of `local` with the constant `value`, except for
+/// captures by closures.
+fn substitute(expr: &mut Expr, local: u32, value: &Expr) {
+    if expr.kind == Kind::Local(local) {
+        expr.kind = value.kind.clone();
+        expr.ty = value.ty;
+    }
+    for child in expr.children_mut() {
+        substitute(child, local, value);
+    }
+}
+
+/// Returns whether `local` is used in `expr`.
+fn uses(expr: &Expr, local: u32) -> bool {
+    match &expr.kind {
+        Kind::Local(idx) => *idx == local,
+        Kind::Closure { captured, .. } => captured.contains(&local),
+        _ => expr.children().into_iter().any(|child| uses(child, local)),
+    }
+}
+
+/// Moves all locals of `expr` by `offset` and attributes it to
+/// `region`, which is where it is inlined.
+fn relocate(expr: &mut Expr, offset: u32, region: Option<Region>) {
+    expr.region = region;
+    match &mut expr.kind {
+        Kind::Local(idx) => *idx += offset,
+        Kind::Let { local, .. } => *local += offset,
+        Kind::Closure { captured, .. } => {
+            for idx in captured {
+                *idx += offset;
+            }
+        }
+        _ => {}
+    }
+    for child in expr.children_mut() {
+        relocate(child, offset, region);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::compile::ir::{Expr, Function, Kind, Local, Op, Type};
+    use crate::compile::optimize::{OptLevel, Optimizer};
+    use crate::reporting::Region;
+
+    #[test]
+    fn fold_constants_and_branches() {
+        // (let (x 3) (if (< x 5) (+ x 5) y))
+        let region = Region::from((1, 1));
+        let expr = |kind| Box::new(Expr::new(region, kind));
+        let binary = |op, left, right| Kind::Binary { op, left, right };
+        let body = Kind::Let {
+            local: 1,
+            value: expr(Kind::Number(3.0)),
+            body: expr(Kind::If {
+                cond: expr(binary(
+                    Op::Lt,
+                    expr(Kind::Local(1)),
+                    expr(Kind::Number(5.0)),
+                )),
+                then: expr(binary(
+                    Op::Add,
+                    expr(Kind::Local(1)),
+                    expr(Kind::Number(5.0)),
+                )),
+                otherwise: expr(Kind::Local(0)),
+            }),
+        };
+        let mut function = Function {
+            params: 1,
+            locals: ["y", "x"]
+                .map(|name| Local {
+                    name: name.to_string(),
+                    ty: Type::Number,
+                })
+                .to_vec(),
+            body: *expr(body),
+        };
+
+        let mut unoptimized = function.clone();
+        Optimizer::new(OptLevel::O0, std::iter::empty()).optimize(&mut unoptimized);
+        assert_eq!(function, unoptimized);
+
+        Optimizer::new(OptLevel::O1, std::iter::empty()).optimize(&mut function);
+        assert_eq!(Kind::Number(8.0), function.body.kind);
+    }
+}
@@ -0,0 +1,88 @@
+//! Dead code elimination on the level of functions.
+//!
+//! Only functions reachable from the exports, the top-level
+//! expressions or the tests are compiled at all.
+
+use crate::compile::{as_defn, qualified, CORE_NAMESPACE};
+use crate::parse::Expr;
+use crate::project::Namespace;
+use std::collections::{HashMap, HashSet};
+
+/// Returns the qualified names of all functions reachable from the
+/// given functions or expressions, each with the namespace it occurs
+/// in.
+///
+/// A symbol referring to a function counts as a call, even if it is
+/// shadowed by a local, which may keep an unused function, but never
+/// removes a used one.
+pub fn reachable<'a>(
+    namespaces: &[&'a Namespace],
+    functions: Vec<String>,
+    expressions: Vec<(&'a Namespace, &'a Expr)>,
+) -> HashSet<String> {
+    let mut defns: HashMap<String, (&Namespace, &Expr)> = HashMap::new();
+    let mut aliases: HashMap<&str, HashMap<String, String>> = HashMap::new();
+    for namespace in namespaces {
+        for expr in &namespace.module.expressions {
+            if let Some((name, _, body)) = as_defn(expr) {
+                defns.insert(qualified(&namespace.name, name), (namespace, body));
+            }
+        }
+        aliases.insert(&namespace.name, namespace.aliases());
+    }
+
+    let mut reachable = HashSet::new();
+    let mut pending = expressions;
+    for function in functions {
+        if let Some(defn) = defns.get(&function) {
+            pending.push(*defn);
+            reachable.insert(function);
+        }
+    }
+
+    while let Some((namespace, expr)) = pending.pop() {
+        let mut symbols = vec![];
+        collect(expr, &mut symbols);
+        for (qualifier, name) in symbols {
+            let candidates = if qualifier.is_empty() {
+                vec![
+                    qualified(&namespace.name, name),
+                    qualified(CORE_NAMESPACE, name),
+                ]
+            } else {
+                let qualifier = qualifier.join(".");
+                let qualifier = aliases[namespace.name.as_str()]
+                    .get(&qualifier)
+                    .cloned()
+                    .unwrap_or(qualifier);
+                vec![qualified(&qualifier, name)]
+            };
+
+            let Some(function) = candidates.into_iter().find(|name| defns.contains_key(name))
+            else {
+                continue;
+            };
+            if !reachable.contains(&function) {
+                pending.push(defns[&function]);
+                reachable.insert(function);
+            }
+        }
+    }
+
+    reachable
+}
+
+/// Collects the qualifier and name of every symbol in `expr`.
+fn collect<'a>(expr: &'a Expr, symbols: &mut Vec<(&'a [String], &'a str)>) {
+    match expr {
+        Expr::Number { .. } | Expr::Boolean { .. } => {}
+        Expr::Symbol {
+            namespace, value, ..
+        } => symbols.push((namespace, value)),
+        Expr::List { expressions, .. } | Expr::Vector { expressions, .. } => {
+            for expr in expressions {
+                collect(expr, symbols);
+            }
+        }
+    }
+}
@@ -0,0 +1,526 @@
+//! Name resolution, which makes sure every symbol refers to a local, a
+//! function, an import, a builtin or a special form, before any code is
+//! generated.
+//!
+//! Along the way, the shape of every special form and the number of
+//! arguments of every call of a named function are checked.
+
+use crate::compile::runtime::Builtin;
+use crate::compile::{
+    as_defn, as_deftest, is_top_level, qualified, Import, CORE_NAMESPACE, PRIVATE,
+};
+use crate::parse::Expr;
+use crate::project::error::Error;
+use crate::project::Namespace;
+use crate::reporting::Region;
+use std::collections::HashMap;
+
+/// Names of the special forms, which are called like functions.
+const SPECIAL_FORMS: [&str; 37] = [
+    "+",
+    "-",
+    "*",
+    "/",
+    "<",
+    "<=",
+    ">",
+    ">=",
+    "=",
+    "not=",
+    "and",
+    "or",
+    "not",
+    "mod",
+    "rem",
+    "quot",
+    "min",
+    "max",
+    "abs",
+    "sqrt",
+    "floor",
+    "ceil",
+    "neg",
+    "inc",
+    "dec",
+    "bit-and",
+    "bit-or",
+    "bit-xor",
+    "bit-not",
+    "bit-shift-left",
+    "bit-shift-right",
+    "if",
+    "let",
+    "fn",
+    "list",
+    "is",
+    "assert=",
+];
+
+/// Names of the forms, which may only appear at the top level of a
+/// module.
+const DECLARATIONS: [&str; 6] = ["ns", "import", "export", "defn", "defn-", "deftest"];
+
+/// Resolves every symbol in the given namespaces, reporting the first
+/// one, which does not refer to anything, or the first malformed
+/// expression.
+pub fn resolve(namespaces: &[&Namespace], imports: &[Import]) -> Result<(), Error> {
+    let mut functions = HashMap::new();
+    for namespace in namespaces {
+        let mut defined: HashMap<&str, &Region> = HashMap::new();
+        for expr in &namespace.module.expressions {
+            if let Some((name, params, _)) = as_defn(expr) {
+                if let Some(first) = defined.insert(name, expr.region()) {
+                    return Err(Error::Duplicate(
+                        namespace.module.filename.clone(),
+                        *expr.region(),
+                        name.to_string(),
+                        *first,
+                    ));
+                }
+                functions.insert(qualified(&namespace.name, name), params.len());
+            }
+        }
+    }
+    for import in imports {
+        functions.insert(qualified(&import.module, &import.name), import.arity);
+    }
+
+    for namespace in namespaces {
+        let resolver = Resolver {
+            namespace,
+            aliases: namespace.aliases(),
+            functions: &functions,
+        };
+        for expr in &namespace.module.expressions {
+            resolver.check_declaration(expr)?;
+            let mut scope = vec![];
+            if let Some((_, params, body)) = as_defn(expr) {
+                bind_all(params, &mut scope);
+                resolver.check(body, &mut scope)?;
+            } else if let Some((_, body)) = as_deftest(expr) {
+                for expr in body {
+                    resolver.check(expr, &mut scope)?;
+                }
+            } else if is_top_level(expr) {
+                resolver.check(expr, &mut scope)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+struct Resolver<'a> {
+    namespace: &'a Namespace,
+    /// See [`Namespace::aliases`].
+    aliases: HashMap<String, String>,
+    /// Arities of all functions and imports by their qualified name.
+    functions: &'a HashMap<String, usize>,
+}
+
+impl<'a> Resolver<'a> {
+    /// Checks the shape of a top-level declaration.
+    fn check_declaration(&self, expr: &Expr) -> Result<(), Error> {
+        let Expr::List {
+            region,
+            expressions,
+        } = expr
+        else {
+            return Ok(());
+        };
+        let (form, args) = match expressions.as_slice() {
+            [Expr::Symbol {
+                namespace, value, ..
+            }, args @ ..]
+                if namespace.is_empty() =>
+            {
+                (value.as_str(), args)
+            }
+            _ => return Ok(()),
+        };
+
+        match form {
+            "defn" | "defn-" => {
+                let args = match args {
+                    [Expr::Symbol { value, .. }, args @ ..] if value == PRIVATE => args,
+                    args => args,
+                };
+                match args {
+                    // Calls of special forms are never resolved to functions,
+                    // so only `core` may wrap them to make them usable as
+                    // values.
+                    [Expr::Symbol {
+                        region: name,
+                        value,
+                        ..
+                    }, Expr::List { .. }, _]
+                        if SPECIAL_FORMS.contains(&value.as_str())
+                            && self.namespace.name != CORE_NAMESPACE =>
+                    {
+                        Err(self.bad_form(
+                            name,
+                            format!("`{}` is a special form and cannot be redefined", value),
+                        ))
+                    }
+                    [Expr::Symbol { .. }, Expr::List {
+                        expressions: params,
+                        ..
+                    }, _] => self.check_params(params),
+                    _ => Err(self.bad_form(
+                        region,
+                        format!("{} expects a name, a list of parameters and a body", form),
+                    )),
+                }
+            }
+            // Global values are not supported by code generation.
+            "def" => Err(self.bad_form(region, "def is not supported, use defn instead")),
+            "deftest" => match args {
+                [Expr::Symbol { .. }, ..] => Ok(()),
+                _ => Err(self.bad_form(region, "deftest expects a name and a body")),
+            },
+            "import" if args.is_empty() || !args.iter().all(is_name) => {
+                Err(self.bad_form(region, "import expects the names of modules"))
+            }
+            "export" if !args.iter().all(is_name) => {
+                Err(self.bad_form(region, "export expects the names of functions"))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Checks the given expression, where `scope` holds the names of
+    /// the locals in scope.
+    fn check(&self, expr: &'a Expr, scope: &mut Vec<&'a str>) -> Result<(), Error> {
+        match expr {
+            Expr::Number { .. } | Expr::Boolean { .. } => Ok(()),
+            Expr::Symbol { .. } => self.check_symbol(expr, scope, false),
+            Expr::Vector { expressions, .. } => self.check_all(expressions, scope),
+            Expr::List {
+                region,
+                expressions,
+            } => match expressions.as_slice() {
+                [Expr::Symbol {
+                    namespace, value, ..
+                }, args @ ..]
+                    if namespace.is_empty()
+                        && !scope.contains(&value.as_str())
+                        && (SPECIAL_FORMS.contains(&value.as_str())
+                            || DECLARATIONS.contains(&value.as_str())) =>
+                {
+                    self.check_form(region, value, args, scope)
+                }
+                [callee @ Expr::Symbol { .. }, args @ ..] => {
+                    self.check_symbol(callee, scope, true)?;
+                    self.check_arity(region, callee, args.len(), scope)?;
+                    self.check_all(args, scope)
+                }
+                expressions => self.check_all(expressions, scope),
+            },
+        }
+    }
+
+    fn check_all(&self, exprs: &'a [Expr], scope: &mut Vec<&'a str>) -> Result<(), Error> {
+        for expr in exprs {
+            self.check(expr, scope)?;
+        }
+        Ok(())
+    }
+
+    /// Checks the shape of a special form and the expressions in it.
+    fn check_form(
+        &self,
+        region: &Region,
+        form: &str,
+        args: &'a [Expr],
+        scope: &mut Vec<&'a str>,
+    ) -> Result<(), Error> {
+        match (form, args) {
+            (
+                "fn",
+                [Expr::List {
+                    expressions: params,
+                    ..
+                }, body],
+            ) => {
+                self.check_params(params)?;
+                let outer = scope.len();
+                bind_all(params, scope);
+                let result = self.check(body, scope);
+                scope.truncate(outer);
+                result
+            }
+            ("fn", _) => Err(self.bad_form(region, "fn expects a list of parameters and a body")),
+            (
+                "let",
+                [Expr::List {
+                    expressions: bindings,
+                    ..
+                }, body],
+            ) => {
+                let outer = scope.len();
+                for binding in bindings.chunks(2) {
+                    match binding {
+                        [name, value] if is_name(name) => {
+                            self.check(value, scope)?;
+                            bind_all(std::slice::from_ref(name), scope);
+                        }
+                        _ => {
+                            return Err(self.bad_form(
+                                binding[0].region(),
+                                "let expects pairs of a symbol and a value",
+                            ))
+                        }
+                    }
+                }
+                let result = self.check(body, scope);
+                scope.truncate(outer);
+                result
+            }
+            ("let", _) => Err(self.bad_form(region, "let expects a list of bindings and a body")),
+            ("if", [_, _] | [_, _, _])
+            | ("is", [_])
+            | ("assert=", [_, _])
+            | ("list" | "and" | "or", _)
+            | (
+                "not" | "abs" | "sqrt" | "floor" | "ceil" | "neg" | "inc" | "dec" | "bit-not",
+                [_],
+            )
+            | (
+                "+" | "-" | "*" | "/" | "min" | "max" | "<" | "<=" | ">" | ">=" | "=" | "not=",
+                [_, ..],
+            )
+            | ("bit-and" | "bit-or" | "bit-xor", [_, _, ..])
+            | ("mod" | "rem" | "quot" | "bit-shift-left" | "bit-shift-right", [_, _]) => {
+                self.check_all(args, scope)
+            }
+            ("if", _) => {
+                Err(self.bad_form(region, "if expects a condition and one or two branches"))
+            }
+            ("is", _) => Err(self.bad_form(region, "is expects a single value")),
+            ("not" | "abs" | "sqrt" | "floor" | "ceil" | "neg" | "inc" | "dec" | "bit-not", _) => {
+                Err(self.bad_form(region, format!("{} expects a single value", form)))
+            }
+            ("assert=", _) => {
+                Err(self.bad_form(region, "assert= expects an expected and an actual value"))
+            }
+            ("+" | "-" | "*" | "/" | "min" | "max" | "<" | "<=" | ">" | ">=" | "=" | "not=", _) => {
+                Err(self.bad_form(region, format!("{} expects at least one argument", form)))
+            }
+            ("bit-and" | "bit-or" | "bit-xor", _) => {
+                Err(self.bad_form(region, format!("{} expects at least two arguments", form)))
+            }
+            ("mod" | "rem" | "quot" | "bit-shift-left" | "bit-shift-right", _) => {
+                Err(self.bad_form(region, format!("{} expects two arguments", form)))
+            }
+            _ => Err(self.bad_form(region, format!("{} is only allowed at the top level", form))),
+        }
+    }
+
+    fn check_params(&self, params: &[Expr]) -> Result<(), Error> {
+        match params.iter().find(|param| !is_name(param)) {
+            Some(param) => Err(self.bad_form(param.region(), "parameters must be symbols")),
+            None => Ok(()),
+        }
+    }
+
+    /// Checks the number of arguments of a call of a named function.
+    fn check_arity(
+        &self,
+        region: &Region,
+        callee: &Expr,
+        args: usize,
+        scope: &[&str],
+    ) -> Result<(), Error> {
+        let Expr::Symbol {
+            namespace, value, ..
+        } = callee
+        else {
+            return Ok(());
+        };
+
+        let (name, arity) = if namespace.is_empty() {
+            if scope.contains(&value.as_str()) {
+                return Ok(());
+            }
+            let arity = match Builtin::from_name(value) {
+                Some(builtin) => Some(builtin.params().len()),
+                None => self
+                    .function(value)
+                    .and_then(|function| self.functions.get(&function).copied()),
+            };
+            (value.clone(), arity)
+        } else {
+            let qualifier = namespace.join(".");
+            let target = self.aliases.get(&qualifier).unwrap_or(&qualifier);
+            let arity = self.functions.get(&qualified(target, value)).copied();
+            (qualified(&qualifier, value), arity)
+        };
+
+        match arity {
+            Some(arity) if arity != args => Err(Error::Arity(
+                self.namespace.module.filename.clone(),
+                *region,
+                name,
+                arity,
+                args,
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// Checks a symbol, which may name a special form, if it is
+    /// `called`.
+    fn check_symbol(&self, expr: &Expr, scope: &[&str], called: bool) -> Result<(), Error> {
+        let Expr::Symbol {
+            region,
+            namespace,
+            value,
+        } = expr
+        else {
+            return Ok(());
+        };
+
+        let (name, candidates) = if namespace.is_empty() {
+            let is_local = scope.contains(&value.as_str());
+            let is_special_form = called && SPECIAL_FORMS.contains(&value.as_str());
+            let is_builtin = Builtin::from_name(value).is_some();
+            if is_local || is_special_form || is_builtin || self.function(value).is_some() {
+                return Ok(());
+            }
+
+            let mut candidates: Vec<String> = scope.iter().map(|name| name.to_string()).collect();
+            if called {
+                candidates.extend(SPECIAL_FORMS.iter().map(|name| name.to_string()));
+            }
+            candidates.extend(
+                Builtin::ALL
+                    .iter()
+                    .filter(|builtin| Builtin::from_name(builtin.name()).is_some())
+                    .map(|builtin| builtin.name().to_string()),
+            );
+            for namespace in [self.namespace.name.as_str(), CORE_NAMESPACE] {
+                candidates.extend(self.functions_of(namespace));
+            }
+            (value.clone(), candidates)
+        } else {
+            let qualifier = namespace.join(".");
+            let target = self.aliases.get(&qualifier).unwrap_or(&qualifier);
+            if self.functions.contains_key(&qualified(target, value)) {
+                return Ok(());
+            }
+
+            let candidates = self
+                .functions_of(target)
+                .into_iter()
+                .map(|name| qualified(&qualifier, &name))
+                .collect();
+            (qualified(&qualifier, value), candidates)
+        };
+
+        let suggestion = suggest(&name, &candidates);
+        Err(Error::Unbound(
+            self.namespace.module.filename.clone(),
+            *region,
+            name,
+            suggestion,
+        ))
+    }
+
+    fn bad_form(&self, region: &Region, message: impl Into<String>) -> Error {
+        Error::BadForm(
+            self.namespace.module.filename.clone(),
+            *region,
+            message.into(),
+        )
+    }
+
+    /// Returns the qualified name of the function an unqualified symbol
+    /// refers to, which is looked up in the current namespace first and
+    /// in `core` afterwards.
+    fn function(&self, name: &str) -> Option<String> {
+        [self.namespace.name.as_str(), CORE_NAMESPACE]
+            .into_iter()
+            .map(|namespace| qualified(namespace, name))
+            .find(|function| self.functions.contains_key(function))
+    }
+
+    /// Returns the unqualified names of all functions of `namespace`.
+    fn functions_of(&self, namespace: &str) -> Vec<String> {
+        let prefix = qualified(namespace, "");
+        let mut names: Vec<String> = self
+            .functions
+            .keys()
+            .filter_map(|function| function.strip_prefix(&prefix))
+            .map(String::from)
+            .collect();
+        names.sort();
+        names
+    }
+}
+
+/// Returns whether the given expression is an unqualified symbol.
+fn is_name(expr: &Expr) -> bool {
+    matches!(expr, Expr::Symbol { namespace, .. } if namespace.is_empty())
+}
+
+fn bind_all<'a>(names: &'a [Expr], scope: &mut Vec<&'a str>) {
+    for name in names {
+        if let Expr::Symbol { value, .. } = name {
+            scope.push(value);
+        }
+    }
+}
+
+/// Returns the candidate closest to `name`, if it is close enough to be
+/// a likely typo, which leaves most of `name` intact.
+fn suggest(name: &str, candidates: &[String]) -> Option<String> {
+    let len = name.chars().count();
+    let max = (len / 3).max(1);
+    candidates
+        .iter()
+        .map(|candidate| (distance(name, candidate), candidate))
+        .filter(|(distance, _)| *distance <= max && *distance < len)
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, candidate)| candidate.clone())
+}
+
+/// Returns the number of insertions, deletions, substitutions and
+/// transpositions of adjacent characters needed to turn `a` into `b`.
+fn distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    let mut d = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for (i, row) in d.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    for (j, cell) in d[0].iter_mut().enumerate() {
+        *cell = j;
+    }
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            d[i][j] = (d[i - 1][j] + 1)
+                .min(d[i][j - 1] + 1)
+                .min(d[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                d[i][j] = d[i][j].min(d[i - 2][j - 2] + 1);
+            }
+        }
+    }
+    d[a.len()][b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::compile::resolve::{distance, suggest};
+
+    #[test]
+    fn suggest_close_names() {
+        let candidates = ["double", "map", "first"].map(String::from);
+
+        assert_eq!(1, distance("doubel", "double"));
+        assert_eq!(Some("double".to_string()), suggest("doubel", &candidates));
+        assert_eq!(Some("first".to_string()), suggest("frist", &candidates));
+        assert_eq!(None, suggest("reduce", &candidates));
+        assert_eq!(None, suggest("p", &candidates));
+    }
+}
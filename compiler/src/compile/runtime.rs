@@ -0,0 +1,336 @@
+//! Functions every compiled module carries with it.
+//!
+//! All values are represented as `f64`. A list is either the empty
+//! list, represented as `0`, or a pointer into linear memory to a cons
+//! cell of 16 bytes, where the first 8 bytes hold the head and the
+//! last 8 bytes hold the tail of the list.
+//!
+//! Memory is handed out by a bump allocator, whose next free address
+//! is kept in the global [`HEAP`]. It starts at [`HEAP_START`], so no
+//! allocation will ever be located at `0`.
+
+use wasm_encoder::{BlockType, Function, Instruction, MemArg, ValType};
+
+/// Index of the global pointing to the next free byte in memory.
+pub const HEAP: u32 = 0;
+
+/// The first address handed out by the allocator.
+pub const HEAP_START: i32 = 8;
+
+/// Size of a cons cell in bytes.
+const CONS_SIZE: i32 = 16;
+
+/// A function provided by the runtime.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Builtin {
+    Alloc,
+    Cons,
+    First,
+    Rest,
+    Count,
+    Nth,
+    IsEmpty,
+    Apply,
+}
+
+impl Builtin {
+    /// All builtins, in the order of their function index.
+    pub const ALL: [Builtin; 8] = [
+        Builtin::Alloc,
+        Builtin::Cons,
+        Builtin::First,
+        Builtin::Rest,
+        Builtin::Count,
+        Builtin::Nth,
+        Builtin::IsEmpty,
+        Builtin::Apply,
+    ];
+
+    /// Returns the builtin callable by the given name, if any.
+    ///
+    /// The allocator is not callable from source code.
+    pub fn from_name(name: &str) -> Option<Builtin> {
+        match name {
+            "cons" => Some(Builtin::Cons),
+            "first" => Some(Builtin::First),
+            "rest" => Some(Builtin::Rest),
+            "count" => Some(Builtin::Count),
+            "nth" => Some(Builtin::Nth),
+            "empty?" => Some(Builtin::IsEmpty),
+            "apply" => Some(Builtin::Apply),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Builtin::Alloc => "alloc",
+            Builtin::Cons => "cons",
+            Builtin::First => "first",
+            Builtin::Rest => "rest",
+            Builtin::Count => "count",
+            Builtin::Nth => "nth",
+            Builtin::IsEmpty => "empty?",
+            Builtin::Apply => "apply",
+        }
+    }
+
+    /// Returns the function index of this builtin, given the builtins
+    /// start at index `offset`.
+    pub fn index(self, offset: u32) -> u32 {
+        offset + self as u32
+    }
+
+    pub fn params(self) -> Vec<ValType> {
+        match self {
+            Builtin::Alloc => vec![ValType::I32],
+            Builtin::Cons | Builtin::Nth | Builtin::Apply => vec![ValType::F64, ValType::F64],
+            Builtin::First | Builtin::Rest | Builtin::Count | Builtin::IsEmpty => {
+                vec![ValType::F64]
+            }
+        }
+    }
+
+    pub fn results(self) -> Vec<ValType> {
+        match self {
+            Builtin::Alloc => vec![ValType::I32],
+            _ => vec![ValType::F64],
+        }
+    }
+
+    /// Returns the body of this builtin, given the builtins start at
+    /// index `offset` and `closures` holds the type indices of closure
+    /// functions by their arity.
+    pub fn function(self, offset: u32, closures: &[u32]) -> Function {
+        match self {
+            Builtin::Alloc => alloc(),
+            Builtin::Cons => cons(offset),
+            Builtin::First => load_field(0),
+            Builtin::Rest => load_field(8),
+            Builtin::Count => count(),
+            Builtin::Nth => nth(),
+            Builtin::IsEmpty => is_empty(),
+            Builtin::Apply => apply(offset, closures),
+        }
+    }
+}
+
+/// `(alloc size)` returns the address of `size` fresh bytes, growing
+/// the memory if necessary.
+fn alloc() -> Function {
+    let mut func = Function::new(vec![(1, ValType::I32)]);
+    let ptr = 1;
+    for instr in [
+        Instruction::GlobalGet(HEAP),
+        Instruction::LocalSet(ptr),
+        Instruction::GlobalGet(HEAP),
+        Instruction::LocalGet(0),
+        Instruction::I32Add,
+        Instruction::GlobalSet(HEAP),
+        Instruction::Block(BlockType::Empty),
+        Instruction::Loop(BlockType::Empty),
+        // Done, if the heap still fits into memory.
+        Instruction::GlobalGet(HEAP),
+        Instruction::MemorySize(0),
+        Instruction::I32Const(16),
+        Instruction::I32Shl,
+        Instruction::I32LeU,
+        Instruction::BrIf(1),
+        Instruction::I32Const(1),
+        Instruction::MemoryGrow(0),
+        Instruction::I32Const(-1),
+        Instruction::I32Eq,
+        Instruction::If(BlockType::Empty),
+        Instruction::Unreachable,
+        Instruction::End,
+        Instruction::Br(0),
+        Instruction::End,
+        Instruction::End,
+        Instruction::LocalGet(ptr),
+        Instruction::End,
+    ] {
+        func.instruction(&instr);
+    }
+    func
+}
+
+/// `(cons head tail)` returns a new list starting with `head`.
+fn cons(offset: u32) -> Function {
+    let mut func = Function::new(vec![(1, ValType::I32)]);
+    let ptr = 2;
+    for instr in [
+        Instruction::I32Const(CONS_SIZE),
+        Instruction::Call(Builtin::Alloc.index(offset)),
+        Instruction::LocalTee(ptr),
+        Instruction::LocalGet(0),
+        Instruction::F64Store(mem_arg(0)),
+        Instruction::LocalGet(ptr),
+        Instruction::LocalGet(1),
+        Instruction::F64Store(mem_arg(8)),
+        Instruction::LocalGet(ptr),
+        Instruction::F64ConvertI32U,
+        Instruction::End,
+    ] {
+        func.instruction(&instr);
+    }
+    func
+}
+
+/// Loads the field at `offset` of the cons cell, or returns the empty
+/// list, if the list is empty.
+fn load_field(offset: u64) -> Function {
+    let mut func = Function::new(vec![]);
+    for instr in [
+        Instruction::LocalGet(0),
+        Instruction::F64Const(0.0),
+        Instruction::F64Eq,
+        Instruction::If(BlockType::Result(ValType::F64)),
+        Instruction::F64Const(0.0),
+        Instruction::Else,
+        Instruction::LocalGet(0),
+        Instruction::I32TruncF64U,
+        Instruction::F64Load(mem_arg(offset)),
+        Instruction::End,
+        Instruction::End,
+    ] {
+        func.instruction(&instr);
+    }
+    func
+}
+
+/// `(count list)` returns the number of elements in the list.
+fn count() -> Function {
+    let mut func = Function::new(vec![(1, ValType::F64)]);
+    let n = 1;
+    for instr in [
+        Instruction::Block(BlockType::Empty),
+        Instruction::Loop(BlockType::Empty),
+        Instruction::LocalGet(0),
+        Instruction::F64Const(0.0),
+        Instruction::F64Eq,
+        Instruction::BrIf(1),
+        Instruction::LocalGet(n),
+        Instruction::F64Const(1.0),
+        Instruction::F64Add,
+        Instruction::LocalSet(n),
+        Instruction::LocalGet(0),
+        Instruction::I32TruncF64U,
+        Instruction::F64Load(mem_arg(8)),
+        Instruction::LocalSet(0),
+        Instruction::Br(0),
+        Instruction::End,
+        Instruction::End,
+        Instruction::LocalGet(n),
+        Instruction::End,
+    ] {
+        func.instruction(&instr);
+    }
+    func
+}
+
+/// `(nth list n)` returns the element at index `n`, trapping if the
+/// list is too short.
+fn nth() -> Function {
+    let mut func = Function::new(vec![]);
+    for instr in [
+        Instruction::Block(BlockType::Empty),
+        Instruction::Loop(BlockType::Empty),
+        Instruction::LocalGet(0),
+        Instruction::F64Const(0.0),
+        Instruction::F64Eq,
+        Instruction::If(BlockType::Empty),
+        Instruction::Unreachable,
+        Instruction::End,
+        Instruction::LocalGet(1),
+        Instruction::F64Const(1.0),
+        Instruction::F64Lt,
+        Instruction::BrIf(1),
+        Instruction::LocalGet(0),
+        Instruction::I32TruncF64U,
+        Instruction::F64Load(mem_arg(8)),
+        Instruction::LocalSet(0),
+        Instruction::LocalGet(1),
+        Instruction::F64Const(1.0),
+        Instruction::F64Sub,
+        Instruction::LocalSet(1),
+        Instruction::Br(0),
+        Instruction::End,
+        Instruction::End,
+        Instruction::LocalGet(0),
+        Instruction::I32TruncF64U,
+        Instruction::F64Load(mem_arg(0)),
+        Instruction::End,
+    ] {
+        func.instruction(&instr);
+    }
+    func
+}
+
+/// `(empty? list)` returns `1`, if the list is empty, `0` otherwise.
+fn is_empty() -> Function {
+    let mut func = Function::new(vec![]);
+    for instr in [
+        Instruction::LocalGet(0),
+        Instruction::F64Const(0.0),
+        Instruction::F64Eq,
+        Instruction::F64ConvertI32U,
+        Instruction::End,
+    ] {
+        func.instruction(&instr);
+    }
+    func
+}
+
+/// `(apply f args)` calls the closure `f` with the elements of the list
+/// `args`, trapping if no closure function takes that many arguments
+/// or `f` takes a different number.
+///
+/// Each arity in `closures` gets a block, the innermost one for no
+/// arguments, which `br_table` breaks out of to reach its call.
+fn apply(offset: u32, closures: &[u32]) -> Function {
+    let mut func = Function::new(vec![]);
+    func.instruction(&Instruction::Block(BlockType::Empty));
+    for _ in closures {
+        func.instruction(&Instruction::Block(BlockType::Empty));
+    }
+    let targets: Vec<u32> = (0..closures.len() as u32).collect();
+    for instr in [
+        Instruction::LocalGet(1),
+        Instruction::Call(Builtin::Count.index(offset)),
+        Instruction::I32TruncF64U,
+        Instruction::BrTable(targets.into(), closures.len() as u32),
+    ] {
+        func.instruction(&instr);
+    }
+    for (arity, ty) in closures.iter().enumerate() {
+        func.instruction(&Instruction::End);
+        func.instruction(&Instruction::LocalGet(0));
+        for idx in 0..arity {
+            func.instruction(&Instruction::LocalGet(1));
+            func.instruction(&Instruction::F64Const(idx as f64));
+            func.instruction(&Instruction::Call(Builtin::Nth.index(offset)));
+        }
+        for instr in [
+            Instruction::LocalGet(0),
+            Instruction::I32TruncF64U,
+            Instruction::F64Load(mem_arg(0)),
+            Instruction::I32TruncF64U,
+            Instruction::CallIndirect { ty: *ty, table: 0 },
+            Instruction::Return,
+        ] {
+            func.instruction(&instr);
+        }
+    }
+    func.instruction(&Instruction::End);
+    func.instruction(&Instruction::Unreachable);
+    func.instruction(&Instruction::End);
+    func
+}
+
+fn mem_arg(offset: u64) -> MemArg {
+    MemArg {
+        offset,
+        align: 3,
+        memory_index: 0,
+    }
+}
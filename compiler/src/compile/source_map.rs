@@ -0,0 +1,202 @@
+//! Source maps for compiled modules.
+//!
+//! A source map of a WebAssembly module consists of a single line,
+//! where the column of each mapping is the offset of an instruction
+//! in the module, see <https://sourcemaps.info/spec.html>.
+
+use crate::compile::Origin;
+use serde::Serialize;
+use wasmparser::{Parser, Payload, TypeRef};
+
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// A source map in version 3 of the format, which serializes to JSON.
+#[derive(Debug, Serialize)]
+pub struct SourceMap {
+    pub version: u32,
+    pub sources: Vec<String>,
+    pub names: Vec<String>,
+    pub mappings: String,
+}
+
+/// A position in a module mapped to a position in its source.
+#[derive(Debug, PartialEq)]
+struct Mapping {
+    offset: usize,
+    source: usize,
+    /// Zero-based line in the source.
+    line: usize,
+    /// Zero-based column in the source.
+    col: usize,
+}
+
+impl SourceMap {
+    /// Returns the source map of the given module, given the origin of
+    /// each function by function index.
+    pub(super) fn new(bytes: &[u8], origins: &[Origin]) -> Self {
+        let mut sources: Vec<String> = vec![];
+        let mut mappings = vec![];
+        for (idx, start) in bodies(bytes) {
+            let Some(origin) = origins.get(idx) else {
+                continue;
+            };
+            let Some(filename) = &origin.filename else {
+                continue;
+            };
+            let source = match sources.iter().position(|source| source == filename) {
+                Some(source) => source,
+                None => {
+                    sources.push(filename.clone());
+                    sources.len() - 1
+                }
+            };
+            for (offset, region) in &origin.offsets {
+                mappings.push(Mapping {
+                    offset: start + offset,
+                    source,
+                    line: region.start.line.saturating_sub(1),
+                    col: region.start.col.saturating_sub(1),
+                });
+            }
+        }
+
+        SourceMap {
+            version: 3,
+            sources,
+            names: vec![],
+            mappings: encode(&mappings),
+        }
+    }
+}
+
+/// Returns the index and offset of the body of every function defined
+/// in the given module.
+fn bodies(bytes: &[u8]) -> Vec<(usize, usize)> {
+    let mut imports = 0;
+    let mut bodies = vec![];
+    for payload in Parser::new(0).parse_all(bytes) {
+        match payload {
+            Ok(Payload::ImportSection(reader)) => {
+                // Only imported functions precede the defined ones.
+                imports = reader
+                    .into_iter()
+                    .filter_map(Result::ok)
+                    .filter(|import| matches!(import.ty, TypeRef::Func(_)))
+                    .count();
+            }
+            Ok(Payload::CodeSectionEntry(body)) => {
+                bodies.push((imports + bodies.len(), body.range().start));
+            }
+            Ok(_) => {}
+            Err(_) => break,
+        }
+    }
+    bodies
+}
+
+/// Encodes the given mappings, ordered by offset, as a single line of
+/// segments, each consisting of the offset, the source, the line and
+/// the column relative to the previous segment.
+fn encode(mappings: &[Mapping]) -> String {
+    let mut encoded = String::new();
+    let mut previous = [0i64; 4];
+    for (i, mapping) in mappings.iter().enumerate() {
+        if i > 0 {
+            encoded.push(',');
+        }
+        let fields = [
+            mapping.offset as i64,
+            mapping.source as i64,
+            mapping.line as i64,
+            mapping.col as i64,
+        ];
+        for (field, previous) in fields.iter().zip(previous.iter_mut()) {
+            vlq(field - *previous, &mut encoded);
+            *previous = *field;
+        }
+    }
+    encoded
+}
+
+/// Appends the given value as a base 64 VLQ, where the least
+/// significant bit of the first digit is the sign.
+fn vlq(value: i64, encoded: &mut String) {
+    let mut rest = if value < 0 {
+        ((-value as u64) << 1) | 1
+    } else {
+        (value as u64) << 1
+    };
+    loop {
+        let mut digit = rest & 0b11111;
+        rest >>= 5;
+        if rest > 0 {
+            digit |= 0b100000;
+        }
+        encoded.push(BASE64[digit as usize] as char);
+        if rest == 0 {
+            break;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::compile::source_map::{bodies, encode, Mapping};
+    use wasm_encoder::{
+        CodeSection, EntityType, Function, FunctionSection, ImportSection, Instruction, MemoryType,
+        Module, TypeSection,
+    };
+
+    #[test]
+    fn encode_mappings() {
+        let mappings = [
+            Mapping {
+                offset: 16,
+                source: 0,
+                line: 0,
+                col: 1,
+            },
+            Mapping {
+                offset: 20,
+                source: 0,
+                line: 2,
+                col: 0,
+            },
+        ];
+
+        assert_eq!("gBAAC,IAED", encode(&mappings));
+    }
+
+    #[test]
+    fn index_bodies_after_function_imports() {
+        let mut types = TypeSection::new();
+        types.function([], []);
+        let mut imports = ImportSection::new();
+        let memory = MemoryType {
+            minimum: 1,
+            maximum: None,
+            memory64: false,
+            shared: false,
+        };
+        imports.import("env", "memory", memory);
+        imports.import("env", "log", EntityType::Function(0));
+        let mut functions = FunctionSection::new();
+        functions.function(0);
+        let mut code = CodeSection::new();
+        let mut body = Function::new([]);
+        body.instruction(&Instruction::End);
+        code.function(&body);
+
+        let mut module = Module::new();
+        module
+            .section(&types)
+            .section(&imports)
+            .section(&functions)
+            .section(&code);
+        let indices: Vec<usize> = bodies(&module.finish())
+            .iter()
+            .map(|(idx, _)| *idx)
+            .collect();
+        assert_eq!(vec![1], indices);
+    }
+}
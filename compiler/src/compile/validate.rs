@@ -0,0 +1,103 @@
+//! Validation of compiled modules.
+//!
+//! Every module the compiler produces should be valid, so a validation
+//! failure is reported as a bug in the compiler, pointing at the
+//! definition of the function it occurred in.
+
+use crate::compile::Origin;
+use crate::project::error::Error;
+use wasmparser::{Parser, Payload, TypeRef, Validator};
+
+/// Validates the given module, given the origin of each function by
+/// function index.
+pub fn validate(bytes: &[u8], origins: &[Origin]) -> Result<(), Error> {
+    let err = match Validator::new().validate_all(bytes) {
+        Ok(_) => return Ok(()),
+        Err(err) => err,
+    };
+
+    let origin = function_at(bytes, err.offset()).and_then(|idx| origins.get(idx));
+    Err(match origin {
+        Some(origin) => Error::CompilerBug(
+            origin.filename.clone(),
+            origin.region,
+            origin.name.clone(),
+            err.message().to_string(),
+        ),
+        None => Error::CompilerBug(None, None, "<module>".to_string(), err.to_string()),
+    })
+}
+
+/// Returns the index of the function, whose body contains the given
+/// offset.
+fn function_at(bytes: &[u8], offset: usize) -> Option<usize> {
+    let mut imports = 0;
+    let mut idx = 0;
+    for payload in Parser::new(0).parse_all(bytes) {
+        match payload.ok()? {
+            Payload::ImportSection(reader) => {
+                for import in reader {
+                    if matches!(import.ok()?.ty, TypeRef::Func(_)) {
+                        imports += 1;
+                    }
+                }
+            }
+            Payload::CodeSectionEntry(body) => {
+                if body.range().contains(&offset) {
+                    return Some(imports + idx);
+                }
+                idx += 1;
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::compile::validate::validate;
+    use crate::compile::Origin;
+    use crate::project::error::Error;
+    use crate::reporting::Region;
+    use wasm_encoder::{
+        CodeSection, Function, FunctionSection, Instruction, Module, TypeSection, ValType,
+    };
+
+    #[test]
+    fn validate_reports_invalid_functions() {
+        let mut types = TypeSection::new();
+        types.function(vec![], vec![ValType::F64]);
+        let mut functions = FunctionSection::new();
+        let mut code = CodeSection::new();
+        for instructions in [vec![Instruction::F64Const(1.0)], vec![]] {
+            functions.function(0);
+            let mut func = Function::new(vec![]);
+            for instr in instructions.iter().chain([&Instruction::End]) {
+                func.instruction(instr);
+            }
+            code.function(&func);
+        }
+        let mut module = Module::new();
+        module.section(&types).section(&functions).section(&code);
+        let bytes = module.finish();
+
+        let region = Region::new(2, 1, 3, 14);
+        let origins = [
+            Origin::generated("user/ok".to_string()),
+            Origin {
+                filename: Some("broken.edn".to_string()),
+                region: Some(region),
+                ..Origin::generated("user/broken".to_string())
+            },
+        ];
+        match validate(&bytes, &origins) {
+            Err(Error::CompilerBug(filename, Some(actual), function, _)) => {
+                assert_eq!(Some("broken.edn".to_string()), filename);
+                assert_eq!(region, actual);
+                assert_eq!("user/broken", function);
+            }
+            result => panic!("Expected a compiler bug, got {:?}", result),
+        }
+    }
+}
@@ -1,17 +1,33 @@
+use crate::compile::{Options, Output};
 use crate::parse::Module;
+use crate::project::Loader;
 use parse::error;
+use std::path::Path;
 
 pub mod compile;
 pub mod parse;
+pub mod project;
 pub mod reporting;
 
 pub fn parse(filename: Option<String>, input: &str) -> Result<Module, error::Error> {
     parse::parse(filename, input)
 }
 
-pub fn compile(filename: Option<String>, input: &str) -> Result<Vec<u8>, error::Error> {
+pub fn compile(filename: Option<String>, input: &str) -> Result<Vec<u8>, project::error::Error> {
     compile::compile(filename, input)
 }
 
+pub fn compile_file(path: &Path, options: &Options) -> Result<Output, project::error::Error> {
+    compile::compile_file(path, options)
+}
+
+pub fn compile_project<L: Loader>(
+    entry: &str,
+    loader: &L,
+    options: &Options,
+) -> Result<Output, project::error::Error> {
+    compile::compile_project(entry, loader, options)
+}
+
 #[cfg(test)]
 mod tests {}
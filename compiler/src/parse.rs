@@ -1,10 +1,13 @@
+pub mod cst;
 pub mod error;
+pub mod format;
+pub mod incremental;
 pub mod lexer;
-mod token;
+pub mod token;
 
-use crate::parse::lexer::{lexer, LexResult};
+use crate::parse::lexer::{lexer_from, LexResult, Lexeme};
 use crate::parse::token::Token;
-use crate::reporting::Region;
+use crate::reporting::{Locator, Position, Region, SourceFile, Span};
 use error::Error;
 use serde::{Deserialize, Serialize};
 
@@ -14,13 +17,17 @@ pub struct Module {
     pub expressions: Vec<Expr>,
 }
 
-#[derive(Debug, Deserialize, Serialize, PartialEq)]
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
 #[serde(tag = "type")]
 pub enum Expr {
     Number {
         region: Region,
         value: f64,
     },
+    Boolean {
+        region: Region,
+        value: bool,
+    },
     Symbol {
         region: Region,
         namespace: Vec<String>,
@@ -30,15 +37,29 @@ pub enum Expr {
         region: Region,
         expressions: Vec<Expr>,
     },
+    Vector {
+        region: Region,
+        expressions: Vec<Expr>,
+    },
 }
 
 impl Expr {
+    pub fn region(&self) -> &Region {
+        match self {
+            Expr::Number { region, .. } => region,
+            Expr::Boolean { region, .. } => region,
+            Expr::Symbol { region, .. } => region,
+            Expr::List { region, .. } => region,
+            Expr::Vector { region, .. } => region,
+        }
+    }
+
     pub fn is_defn(&self) -> bool {
         match self {
             Expr::List { expressions, .. } => expressions
                 .first()
                 .map(|form| match form {
-                    Expr::Symbol { value, .. } => value == "defn",
+                    Expr::Symbol { value, .. } => value == "defn" || value == "defn-",
                     _ => false,
                 })
                 .unwrap_or(false),
@@ -50,92 +71,141 @@ impl Expr {
 // PARSING
 
 pub fn parse(filename: Option<String>, input: &str) -> Result<Module, Error> {
-    let mut parser = Parser::new(lexer(input));
-    parser.module(filename)
+    let file = SourceFile::new(filename, input);
+    let expressions = forms(&file, 0)
+        .map(|form| form.map(|(_, expr)| expr))
+        .collect::<Result<_, _>>()?;
+    Ok(Module {
+        filename: file.name,
+        expressions,
+    })
+}
+
+/// Returns the top-level forms of the file starting at the given byte
+/// offset together with their spans.
+pub(crate) fn forms(
+    file: &SourceFile,
+    offset: usize,
+) -> impl Iterator<Item = Result<(Span, Expr), Error>> + '_ {
+    let mut parser = Parser::new(file, lexer_from(file.source(), offset));
+    std::iter::from_fn(move || parser.form())
 }
 
-struct Parser<T: Iterator<Item = LexResult>> {
+struct Parser<'a, T: Iterator<Item = LexResult<'a>>> {
+    /// Converts the spans of tokens, which are increasing, into regions.
+    locator: Locator<'a>,
     input: T,
-    token0: Option<(Region, Token)>,
-    errors: Vec<Error>,
+    token0: Option<Lexeme<'a>>,
+    /// Byte offset after the last token.
+    end: usize,
+    /// Byte offset of the end of the input.
+    length: usize,
 }
 
-impl<T: Iterator<Item = LexResult>> Parser<T> {
-    fn new(input: T) -> Self {
+impl<'a, T: Iterator<Item = LexResult<'a>>> Parser<'a, T> {
+    fn new(file: &'a SourceFile, input: T) -> Self {
         Parser {
+            locator: file.locator(),
             token0: None,
-            errors: vec![],
+            end: 0,
+            length: file.source().len(),
             input,
         }
     }
 
-    fn module(&mut self, filename: Option<String>) -> Result<Module, Error> {
+    /// Parses the next top-level form together with its span.
+    fn form(&mut self) -> Option<Result<(Span, Expr), Error>> {
+        let lexeme = match self.advance()? {
+            Ok(lexeme) => lexeme,
+            Err(error) => return Some(Err(error)),
+        };
+        let start = lexeme.span.start;
+        Some(
+            self.expr(lexeme)
+                .map(|expr| (Span::new(start, self.end), expr)),
+        )
+    }
+
+    fn expr(&mut self, lexeme: Lexeme<'a>) -> Result<Expr, Error> {
+        let region = self.locator.region(lexeme.span);
+        match lexeme.token {
+            Token::Number(value) => Ok(Expr::Number { region, value }),
+            Token::Boolean(value) => Ok(Expr::Boolean { region, value }),
+            Token::Symbol(symbol) => {
+                let (namespace, value) = split_symbol(symbol);
+                Ok(Expr::Symbol {
+                    region,
+                    namespace,
+                    value,
+                })
+            }
+            Token::LParen => {
+                let (region, expressions) = self.sequence(region, Token::RParen)?;
+                Ok(Expr::List {
+                    region,
+                    expressions,
+                })
+            }
+            Token::LBracket => {
+                let (region, expressions) = self.sequence(region, Token::RBracket)?;
+                Ok(Expr::Vector {
+                    region,
+                    expressions,
+                })
+            }
+            Token::RParen | Token::RBracket => Err(self.error(lexeme.span.start)),
+        }
+    }
+
+    /// Parses expressions up to the `close` token, returning them
+    /// together with the region from `start` up to `close`.
+    fn sequence(&mut self, start: Region, close: Token<'a>) -> Result<(Region, Vec<Expr>), Error> {
         let mut expressions = vec![];
         loop {
-            match self.advance() {
-                None => {
-                    return Ok(Module {
-                        filename,
+            match self.advance().transpose()? {
+                None => return Err(self.error(self.length)),
+                Some(lexeme) if lexeme.token == close => {
+                    let end = self.locator.region(lexeme.span).end;
+                    return Ok((
+                        Region::new(start.start.line, start.start.col, end.line, end.col),
                         expressions,
-                    })
+                    ));
                 }
-                Some(token) => match self.expr(token) {
-                    Err(error) => return Err(error),
-                    Ok(expr) => expressions.push(expr),
-                },
+                Some(lexeme) => expressions.push(self.expr(lexeme)?),
             }
         }
     }
 
-    fn expr(&mut self, token: (Region, Token)) -> Result<Expr, Error> {
-        match token {
-            (region, Token::Number(value)) => Ok(Expr::Number { region, value }),
-            (region, Token::Symbol(namespace, value)) => Ok(Expr::Symbol {
-                region,
-                namespace,
-                value,
-            }),
-            (region, Token::LParen) => {
-                let mut expressions = vec![];
-                loop {
-                    match self.advance() {
-                        None => return Err(Error::BadEndOfInput(0, 0)),
-                        Some((end_region, Token::RParen)) => {
-                            let region = Region::new(
-                                region.start.line,
-                                region.start.col,
-                                end_region.end.line,
-                                end_region.end.col,
-                            );
-
-                            return Ok(Expr::List {
-                                region,
-                                expressions,
-                            });
-                        }
-                        Some(token) => expressions.push(self.expr(token)?),
-                    }
-                }
-            }
-            (_, Token::Eof) | (_, Token::RParen) => Err(Error::BadEndOfInput(0, 0)),
+    /// Returns the next lexeme or the error of the lexer.
+    fn advance(&mut self) -> Option<LexResult<'a>> {
+        let lexeme = match self.token0.take() {
+            None => self.input.next()?,
+            Some(value) => Ok(value),
+        };
+        if let Ok(lexeme) = &lexeme {
+            self.end = lexeme.span.end;
         }
+        Some(lexeme)
     }
 
-    fn advance(&mut self) -> Option<(Region, Token)> {
-        match self.token0.take() {
-            None => self.next(),
-            Some(value) => Some(value),
-        }
+    /// Returns the error for an unexpected closing bracket or end of
+    /// input at the given byte offset.
+    fn error(&mut self, offset: usize) -> Error {
+        let Position { line, col } = self.locator.position(offset);
+        Error::BadEndOfInput(line, col)
     }
+}
 
-    fn next(&mut self) -> Option<(Region, Token)> {
-        loop {
-            match self.input.next() {
-                None => return None,
-                Some(Ok(result)) => return Some(result),
-                Some(Err(error)) => self.errors.push(error),
-            }
-        }
+/// Splits a symbol like `my.util/double` into its namespace and name.
+///
+/// A lone `/` is the name of the division.
+pub(crate) fn split_symbol(symbol: &str) -> (Vec<String>, String) {
+    match symbol.rsplit_once('/') {
+        Some((namespace, name)) if symbol.len() > 1 => (
+            namespace.split('/').map(String::from).collect(),
+            name.to_string(),
+        ),
+        _ => (vec![], symbol.to_string()),
     }
 }
 
@@ -168,6 +238,44 @@ mod tests {
         assert_eq!(expected, actual)
     }
 
+    #[test]
+    fn parse_vector_test() {
+        let actual = parse(None, "(ns a [b])").unwrap().expressions;
+        let expected: Vec<Expr> = vec![list(
+            (1, 1, 10),
+            vec![
+                sym((1, 2, 3), "ns"),
+                sym((1, 5, 5), "a"),
+                vector((1, 7, 9), vec![sym((1, 8, 8), "b")]),
+            ],
+        )];
+
+        assert_eq!(expected, actual)
+    }
+
+    #[test]
+    fn parse_multi_byte_characters() {
+        let actual = parse(None, "(größe\n  ü)").unwrap().expressions;
+        let expected: Vec<Expr> = vec![list(
+            (1, 1, 2, 4),
+            vec![sym((1, 2, 6), "größe"), sym((2, 3, 3), "ü")],
+        )];
+
+        assert_eq!(expected, actual)
+    }
+
+    #[test]
+    fn report_unbalanced_brackets() {
+        let error = parse(None, "(defn main () (+ 1 2)").unwrap_err();
+        assert_eq!(
+            Some(Region::from((1, 22))),
+            error.to_diagnostic(None).region
+        );
+
+        let error = parse(None, "(+ 1 2))").unwrap_err();
+        assert_eq!(Some(Region::from((1, 8))), error.to_diagnostic(None).region);
+    }
+
     fn list<R: Into<Region>>(region: R, expressions: Vec<Expr>) -> Expr {
         Expr::List {
             region: region.into(),
@@ -175,6 +283,13 @@ mod tests {
         }
     }
 
+    fn vector<R: Into<Region>>(region: R, expressions: Vec<Expr>) -> Expr {
+        Expr::Vector {
+            region: region.into(),
+            expressions,
+        }
+    }
+
     fn num<R: Into<Region>>(region: R, value: f64) -> Expr {
         Expr::Number {
             region: region.into(),
@@ -0,0 +1,327 @@
+//! A lossless concrete syntax tree, which keeps the whitespace around
+//! every token, so tools can rewrite a module without reformatting it.
+//!
+//! The whitespace after a token up to the end of its line is trailing
+//! trivia of the token, all other whitespace is leading trivia of the
+//! next token. Whitespace at the end of the input belongs to the tree.
+
+use crate::parse::error::Error;
+use crate::parse::lexer::{lexer, Lexeme};
+use crate::parse::token::Token;
+use crate::parse::{split_symbol, Expr, Module};
+use crate::reporting::{Locator, Position, Region, SourceFile, Span};
+use serde::Serialize;
+use std::fmt;
+use std::fmt::{Display, Formatter};
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SyntaxTree {
+    pub nodes: Vec<SyntaxNode>,
+    /// Whitespace after the last node.
+    pub trailing: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "type")]
+pub enum SyntaxNode {
+    /// A number or a symbol.
+    Atom(SyntaxToken),
+    List {
+        open: SyntaxToken,
+        nodes: Vec<SyntaxNode>,
+        close: SyntaxToken,
+    },
+    Vector {
+        open: SyntaxToken,
+        nodes: Vec<SyntaxNode>,
+        close: SyntaxToken,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SyntaxToken {
+    pub kind: SyntaxKind,
+    pub leading: String,
+    pub text: String,
+    pub trailing: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum SyntaxKind {
+    LParen,
+    RParen,
+    LBracket,
+    RBracket,
+    Symbol,
+    Number,
+    Boolean,
+}
+
+/// Parses the input into a syntax tree, which prints as exactly the
+/// input again.
+pub fn parse(input: &str) -> Result<SyntaxTree, Error> {
+    let mut lexemes = vec![];
+    for lexeme in lexer(input) {
+        lexemes.push(lexeme?);
+    }
+
+    let mut builder = Builder {
+        input,
+        file: SourceFile::new(None, input),
+        lexemes: lexemes.into_iter().peekable(),
+        offset: 0,
+    };
+    let mut nodes = vec![];
+    while let Some(lexeme) = builder.lexemes.next() {
+        nodes.push(builder.node(lexeme)?);
+    }
+    Ok(SyntaxTree {
+        nodes,
+        trailing: input[builder.offset..].to_string(),
+    })
+}
+
+struct Builder<'a> {
+    input: &'a str,
+    file: SourceFile,
+    lexemes: std::iter::Peekable<std::vec::IntoIter<Lexeme<'a>>>,
+    /// Byte offset after the trivia of the previous token.
+    offset: usize,
+}
+
+impl<'a> Builder<'a> {
+    fn node(&mut self, lexeme: Lexeme<'a>) -> Result<SyntaxNode, Error> {
+        let span = lexeme.span;
+        let (close, vector) = match lexeme.token {
+            Token::Number(_) => return Ok(SyntaxNode::Atom(self.token(SyntaxKind::Number, span))),
+            Token::Symbol(_) => return Ok(SyntaxNode::Atom(self.token(SyntaxKind::Symbol, span))),
+            Token::Boolean(_) => {
+                return Ok(SyntaxNode::Atom(self.token(SyntaxKind::Boolean, span)))
+            }
+            Token::LParen => (Token::RParen, false),
+            Token::LBracket => (Token::RBracket, true),
+            Token::RParen | Token::RBracket => return Err(self.error(span.start)),
+        };
+
+        let open = self.token(
+            if vector {
+                SyntaxKind::LBracket
+            } else {
+                SyntaxKind::LParen
+            },
+            span,
+        );
+        let mut nodes = vec![];
+        loop {
+            match self.lexemes.next() {
+                None => return Err(self.error(self.input.len())),
+                Some(lexeme) if lexeme.token == close => {
+                    let kind = if vector {
+                        SyntaxKind::RBracket
+                    } else {
+                        SyntaxKind::RParen
+                    };
+                    let close = self.token(kind, lexeme.span);
+                    return Ok(if vector {
+                        SyntaxNode::Vector { open, nodes, close }
+                    } else {
+                        SyntaxNode::List { open, nodes, close }
+                    });
+                }
+                Some(lexeme) => nodes.push(self.node(lexeme)?),
+            }
+        }
+    }
+
+    /// Returns the token at `span` with the trivia since the previous
+    /// token and up to the end of its line.
+    fn token(&mut self, kind: SyntaxKind, span: Span) -> SyntaxToken {
+        let next = match self.lexemes.peek() {
+            Some(lexeme) => lexeme.span.start,
+            None => self.input.len(),
+        };
+        let gap = &self.input[span.end..next];
+        let trailing = match gap.find('\n') {
+            Some(newline) => &gap[..newline],
+            None if next == self.input.len() => "",
+            None => gap,
+        };
+
+        let token = SyntaxToken {
+            kind,
+            leading: self.input[self.offset..span.start].to_string(),
+            text: self.input[span.start..span.end].to_string(),
+            trailing: trailing.to_string(),
+        };
+        self.offset = span.end + trailing.len();
+        token
+    }
+
+    fn error(&self, offset: usize) -> Error {
+        let Position { line, col } = self.file.position(offset);
+        Error::BadEndOfInput(line, col)
+    }
+}
+
+impl SyntaxTree {
+    /// Returns the module the tree describes, where all regions are
+    /// relative to the printed tree.
+    pub fn module(&self, filename: Option<String>) -> Module {
+        let file = SourceFile::new(filename, self.to_string());
+        let mut converter = Converter {
+            locator: file.locator(),
+            offset: 0,
+        };
+        Module {
+            filename: file.name.clone(),
+            expressions: self.nodes.iter().map(|node| converter.expr(node)).collect(),
+        }
+    }
+}
+
+struct Converter<'a> {
+    locator: Locator<'a>,
+    /// Byte offset of the printed tree after the previous token.
+    offset: usize,
+}
+
+impl<'a> Converter<'a> {
+    fn expr(&mut self, node: &SyntaxNode) -> Expr {
+        match node {
+            SyntaxNode::Atom(token) => {
+                let region = self.token(token);
+                match token.kind {
+                    SyntaxKind::Number => Expr::Number {
+                        region,
+                        value: token.text.parse().unwrap_or(f64::NAN),
+                    },
+                    SyntaxKind::Boolean => Expr::Boolean {
+                        region,
+                        value: token.text == "true",
+                    },
+                    _ => {
+                        let (namespace, value) = split_symbol(&token.text);
+                        Expr::Symbol {
+                            region,
+                            namespace,
+                            value,
+                        }
+                    }
+                }
+            }
+            SyntaxNode::List { open, nodes, close } => {
+                let (region, expressions) = self.sequence(open, nodes, close);
+                Expr::List {
+                    region,
+                    expressions,
+                }
+            }
+            SyntaxNode::Vector { open, nodes, close } => {
+                let (region, expressions) = self.sequence(open, nodes, close);
+                Expr::Vector {
+                    region,
+                    expressions,
+                }
+            }
+        }
+    }
+
+    fn sequence(
+        &mut self,
+        open: &SyntaxToken,
+        nodes: &[SyntaxNode],
+        close: &SyntaxToken,
+    ) -> (Region, Vec<Expr>) {
+        let start = self.token(open).start;
+        let expressions = nodes.iter().map(|node| self.expr(node)).collect();
+        let end = self.token(close).end;
+        (Region { start, end }, expressions)
+    }
+
+    fn token(&mut self, token: &SyntaxToken) -> Region {
+        let start = self.offset + token.leading.len();
+        let end = start + token.text.len();
+        self.offset = end + token.trailing.len();
+        self.locator.region(Span::new(start, end))
+    }
+}
+
+impl Display for SyntaxTree {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        for node in &self.nodes {
+            write!(f, "{}", node)?;
+        }
+        write!(f, "{}", self.trailing)
+    }
+}
+
+impl Display for SyntaxNode {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            SyntaxNode::Atom(token) => write!(f, "{}", token),
+            SyntaxNode::List { open, nodes, close } | SyntaxNode::Vector { open, nodes, close } => {
+                write!(f, "{}", open)?;
+                for node in nodes {
+                    write!(f, "{}", node)?;
+                }
+                write!(f, "{}", close)
+            }
+        }
+    }
+}
+
+impl Display for SyntaxToken {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}{}", self.leading, self.text, self.trailing)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parse;
+    use crate::parse::cst::{SyntaxKind, SyntaxNode};
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn round_trip_with_trivia() {
+        let source = "\n(ns my.app)  \n\n(defn   double [x]\r\n\t(* 2 x) )\n\n";
+        let tree = parse::cst::parse(source).unwrap();
+        assert_eq!(source, tree.to_string());
+        assert_eq!(
+            parse::parse(None, source).unwrap().expressions,
+            tree.module(None).expressions
+        );
+
+        let SyntaxNode::List { open, nodes, .. } = &tree.nodes[0] else {
+            panic!("Expected a list, got {:?}", tree.nodes[0]);
+        };
+        assert_eq!(
+            ("\n", "(", ""),
+            (&*open.leading, &*open.text, &*open.trailing)
+        );
+        let SyntaxNode::Atom(name) = &nodes[1] else {
+            panic!("Expected an atom, got {:?}", nodes[1]);
+        };
+        assert_eq!(SyntaxKind::Symbol, name.kind);
+        assert_eq!(("my.app", ""), (&*name.text, &*name.trailing));
+        assert_eq!("\n\n", tree.trailing);
+    }
+
+    #[test]
+    fn edit_without_reformatting() {
+        let source = "(defn  inc (x)\n  (+ x   1))";
+        let mut tree = parse::cst::parse(source).unwrap();
+        let SyntaxNode::List { nodes, .. } = &mut tree.nodes[0] else {
+            panic!("Expected a list");
+        };
+        if let SyntaxNode::Atom(name) = &mut nodes[1] {
+            name.text = "increment".to_string();
+        }
+
+        assert_eq!("(defn  increment (x)\n  (+ x   1))", tree.to_string());
+        assert_eq!(
+            parse::parse(None, &tree.to_string()).unwrap().expressions,
+            tree.module(None).expressions
+        );
+    }
+}
@@ -1,5 +1,5 @@
+use crate::reporting::{code, Col, Diagnostic, Line, Region};
 use serde::{Deserialize, Serialize};
-use crate::reporting::{Col, Line};
 
 #[derive(Debug, Serialize, Deserialize)]
 pub enum Error {
@@ -8,3 +8,24 @@ pub enum Error {
     BadEndOfInput(Line, Col),
 }
 
+impl Error {
+    /// Returns the diagnostic for this error in the given file.
+    pub fn to_diagnostic(&self, filename: Option<String>) -> Diagnostic {
+        let diagnostic = match self {
+            Error::BadChar(line, col, c) => {
+                Diagnostic::error(code::BAD_CHAR, format!("unexpected character `{}`", c))
+                    .at(Region::from((*line, *col)))
+            }
+            Error::Number(line, col, reason) => {
+                Diagnostic::error(code::BAD_NUMBER, format!("invalid number: {}", reason))
+                    .at(Region::from((*line, *col)))
+            }
+            Error::BadEndOfInput(line, col) => Diagnostic::error(
+                code::BAD_END_OF_INPUT,
+                "unexpected end of input or closing bracket",
+            )
+            .at(Region::from((*line, *col))),
+        };
+        diagnostic.in_file(filename)
+    }
+}
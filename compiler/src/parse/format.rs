@@ -0,0 +1,119 @@
+//! A formatter, which normalizes the whitespace of a module.
+//!
+//! Line breaks are kept, but at most one empty line in a row. A line
+//! is indented two spaces further than the innermost list or vector it
+//! is in, and other tokens are separated by a single space.
+
+use crate::parse::cst;
+use crate::parse::cst::{SyntaxKind, SyntaxNode, SyntaxToken};
+use crate::parse::error::Error;
+
+pub fn format(input: &str) -> Result<String, Error> {
+    let tree = cst::parse(input)?;
+    let mut formatter = Formatter {
+        output: String::with_capacity(input.len()),
+        col: 0,
+        open: vec![],
+    };
+    for (idx, node) in tree.nodes.iter().enumerate() {
+        if idx > 0 {
+            // Top-level forms always start on a new line.
+            let newlines = leading_newlines(node).clamp(1, 2);
+            formatter.newlines(newlines);
+        }
+        formatter.node(node, true);
+    }
+    if !tree.nodes.is_empty() {
+        formatter.output.push('\n');
+    }
+    Ok(formatter.output)
+}
+
+struct Formatter {
+    output: String,
+    /// Column in characters after the last character of the output.
+    col: usize,
+    /// Columns of the open lists and vectors.
+    open: Vec<usize>,
+}
+
+impl Formatter {
+    /// Writes the node, where the whitespace before its first token has
+    /// already been written, if it is `placed`.
+    fn node(&mut self, node: &SyntaxNode, placed: bool) {
+        match node {
+            SyntaxNode::Atom(token) => self.token(token, placed),
+            SyntaxNode::List { open, nodes, close } | SyntaxNode::Vector { open, nodes, close } => {
+                self.token(open, placed);
+                self.open.push(self.col - 1);
+                for node in nodes {
+                    self.node(node, false);
+                }
+                self.open.pop();
+                self.token(close, false);
+            }
+        }
+    }
+
+    fn token(&mut self, token: &SyntaxToken, placed: bool) {
+        if !placed {
+            let newlines = token.leading.matches('\n').count().min(2);
+            if newlines > 0 && !is_close(token.kind) {
+                self.newlines(newlines);
+            } else if !self.output.ends_with(['(', '[']) && !is_close(token.kind) {
+                self.write(" ");
+            }
+        }
+        self.write(&token.text);
+    }
+
+    /// Starts a new line after the given number of line breaks and
+    /// indents it.
+    fn newlines(&mut self, newlines: usize) {
+        for _ in 0..newlines {
+            self.output.push('\n');
+        }
+        self.col = 0;
+        let indent = self.open.last().map_or(0, |col| col + 2);
+        self.write(&" ".repeat(indent));
+    }
+
+    fn write(&mut self, text: &str) {
+        self.output.push_str(text);
+        self.col += text.chars().count();
+    }
+}
+
+fn is_close(kind: SyntaxKind) -> bool {
+    matches!(kind, SyntaxKind::RParen | SyntaxKind::RBracket)
+}
+
+/// Returns the number of line breaks before the first token of the node.
+fn leading_newlines(node: &SyntaxNode) -> usize {
+    let token = match node {
+        SyntaxNode::Atom(token) => token,
+        SyntaxNode::List { open, .. } | SyntaxNode::Vector { open, .. } => open,
+    };
+    token.leading.matches('\n').count()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parse::format::format;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn format_whitespace() {
+        let source = "  (ns my.app) (defn   fact [ n ]\n(if (<= n 1)\n\n\n      1\n\t(* n (fact (- n 1)) )  ))  ";
+        let expected =
+            "(ns my.app)\n(defn fact [n]\n  (if (<= n 1)\n\n    1\n    (* n (fact (- n 1)))))\n";
+        assert_eq!(expected, format(source).unwrap());
+        assert_eq!(expected, format(expected).unwrap());
+    }
+
+    #[test]
+    fn format_core() {
+        let core = include_str!("../compile/core.edn");
+        assert_eq!(core, format(core).unwrap());
+    }
+}
@@ -0,0 +1,250 @@
+//! Incremental reparsing of a module, while it is edited.
+//!
+//! An edit only reparses the top-level forms it touches. Parsing stops
+//! as soon as a reparsed form ends where a form of the previous text
+//! ended, since the following forms are unchanged and only move.
+
+use crate::parse::error::Error;
+use crate::parse::{forms, Expr, Module};
+use crate::reporting::{Region, SourceFile, Span};
+use std::ops::Range;
+
+/// Replaces a span of bytes of the previous text, which must start and
+/// end at character boundaries.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Edit {
+    pub span: Span,
+    pub text: String,
+}
+
+impl Edit {
+    pub fn new(span: Span, text: impl Into<String>) -> Self {
+        Edit {
+            span,
+            text: text.into(),
+        }
+    }
+}
+
+/// A top-level form together with its span.
+type Form = (Span, Expr);
+
+/// The text of a module and its top-level forms.
+#[derive(Debug)]
+pub struct Document {
+    file: SourceFile,
+    forms: Vec<Form>,
+    /// Whether the forms are of an earlier text, because the text does
+    /// not parse.
+    stale: bool,
+}
+
+impl Document {
+    pub fn parse(filename: Option<String>, input: &str) -> Result<Self, Error> {
+        let file = SourceFile::new(filename, input);
+        let forms = forms(&file, 0).collect::<Result<_, _>>()?;
+        Ok(Document {
+            file,
+            forms,
+            stale: false,
+        })
+    }
+
+    pub fn source(&self) -> &str {
+        self.file.source()
+    }
+
+    pub fn expressions(&self) -> impl Iterator<Item = &Expr> {
+        self.forms.iter().map(|(_, expr)| expr)
+    }
+
+    pub fn module(&self) -> Module {
+        Module {
+            filename: self.file.name.clone(),
+            expressions: self.expressions().cloned().collect(),
+        }
+    }
+
+    /// Applies the edit and returns the indices of the reparsed forms.
+    ///
+    /// If the edited text does not parse, the error is returned and the
+    /// forms stay the ones of the last text, which parsed. The next edit
+    /// then reparses the whole text.
+    pub fn edit(&mut self, edit: &Edit) -> Result<Range<usize>, Error> {
+        let old = &self.file;
+        let source = [
+            &old.source()[..edit.span.start],
+            &edit.text,
+            &old.source()[edit.span.end..],
+        ]
+        .concat();
+        let file = SourceFile::new(old.name.clone(), source);
+
+        let (first, rest, reparsed) = if self.stale {
+            (0, self.forms.len(), forms(&file, 0).collect())
+        } else {
+            self.reparse(&file, edit)
+        };
+        let reparsed: Vec<Form> = match reparsed {
+            Ok(reparsed) => reparsed,
+            Err(error) => {
+                self.file = file;
+                self.stale = true;
+                return Err(error);
+            }
+        };
+
+        // Forms after the edit keep their columns and move by the same
+        // number of bytes and lines.
+        let end = edit.span.start + edit.text.len();
+        let bytes = end as isize - edit.span.end as isize;
+        let lines = file.line(end) as isize - self.file.line(edit.span.end) as isize;
+        let moved: Vec<_> = self
+            .forms
+            .drain(rest..)
+            .map(|(span, mut expr)| {
+                shift(&mut expr, lines);
+                let span = Span::new(
+                    span.start.wrapping_add_signed(bytes),
+                    span.end.wrapping_add_signed(bytes),
+                );
+                (span, expr)
+            })
+            .collect();
+
+        let reparsed_len = reparsed.len();
+        self.forms.truncate(first);
+        self.forms.extend(reparsed);
+        self.forms.extend(moved);
+        self.file = file;
+        self.stale = false;
+        Ok(first..first + reparsed_len)
+    }
+
+    /// Reparses the forms touched by the edit, returning the index of
+    /// the first one, the index of the first old form after them, which
+    /// is unchanged, and the reparsed forms.
+    fn reparse(&self, file: &SourceFile, edit: &Edit) -> (usize, usize, Result<Vec<Form>, Error>) {
+        let first = self
+            .forms
+            .partition_point(|(span, _)| span.end < edit.span.start);
+        let start = match self.forms.get(first) {
+            Some((span, _)) => span.start.min(edit.span.start),
+            None => edit.span.start,
+        };
+
+        let end = edit.span.start + edit.text.len();
+        let edited_line = self.file.line(edit.span.end);
+        let mut reparsed = vec![];
+        for form in forms(file, start) {
+            let (span, expr) = match form {
+                Ok(form) => form,
+                Err(error) => return (first, self.forms.len(), Err(error)),
+            };
+            reparsed.push((span, expr));
+            if span.end < end {
+                continue;
+            }
+
+            // The text after the form is the same as after `old_end`.
+            let old_end = span.end - end + edit.span.end;
+            let Some(last) = self.forms[first..]
+                .iter()
+                .position(|(span, _)| span.end == old_end)
+            else {
+                continue;
+            };
+            match self.forms.get(first + last + 1) {
+                None => return (first, self.forms.len(), Ok(reparsed)),
+                // Columns on the edited line change.
+                Some((next, _)) if self.file.line(next.start) > edited_line => {
+                    return (first, first + last + 1, Ok(reparsed))
+                }
+                Some(_) => {}
+            }
+        }
+        (first, self.forms.len(), Ok(reparsed))
+    }
+}
+
+/// Moves all regions of the expression by the given number of lines.
+fn shift(expr: &mut Expr, lines: isize) {
+    let (region, expressions) = match expr {
+        Expr::Number { region, .. }
+        | Expr::Boolean { region, .. }
+        | Expr::Symbol { region, .. } => (region, None),
+        Expr::List {
+            region,
+            expressions,
+        }
+        | Expr::Vector {
+            region,
+            expressions,
+        } => (region, Some(expressions)),
+    };
+    *region = Region::new(
+        region.start.line.wrapping_add_signed(lines),
+        region.start.col,
+        region.end.line.wrapping_add_signed(lines),
+        region.end.col,
+    );
+    for expr in expressions.into_iter().flatten() {
+        shift(expr, lines);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parse;
+    use crate::parse::incremental::{Document, Edit};
+    use crate::reporting::Span;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn reparse_only_edited_forms() {
+        let source = "(defn a () 1)\n(defn b () 2)\n\n(defn c () 3)\n(defn d () 4)";
+        let mut document = Document::parse(None, source).unwrap();
+
+        // Replace `2` by two lines.
+        let reparsed = document
+            .edit(&Edit::new(Span::new(25, 26), "(+ 1\n  1)"))
+            .unwrap();
+        assert_eq!(1..2, reparsed);
+        assert_eq!(
+            "(defn a () 1)\n(defn b () (+ 1\n  1))\n\n(defn c () 3)\n(defn d () 4)",
+            document.source()
+        );
+        assert_module(&document);
+
+        // Insert a form right after `c`, which is reparsed as well.
+        let reparsed = document
+            .edit(&Edit::new(Span::new(50, 50), "\n(defn e () 5)"))
+            .unwrap();
+        assert_eq!(2..4, reparsed);
+        assert_module(&document);
+
+        // Merge `a` and `b` into a single form.
+        let reparsed = document.edit(&Edit::new(Span::new(12, 15), " ")).unwrap();
+        assert_eq!(0..1, reparsed);
+        assert_eq!(4, document.expressions().count());
+        assert_module(&document);
+    }
+
+    #[test]
+    fn recover_from_errors() {
+        let mut document = Document::parse(None, "(a)\n(b)\n(c)").unwrap();
+        assert!(document.edit(&Edit::new(Span::new(4, 5), "")).is_err());
+        assert_eq!(3, document.expressions().count());
+
+        let reparsed = document.edit(&Edit::new(Span::new(4, 4), "(")).unwrap();
+        assert_eq!(0..3, reparsed);
+        assert_eq!("(a)\n(b)\n(c)", document.source());
+    }
+
+    /// Asserts that the document has the same expressions as parsing its
+    /// whole text.
+    fn assert_module(document: &Document) {
+        let expected = parse::parse(None, document.source()).unwrap();
+        assert_eq!(expected.expressions, document.module().expressions);
+    }
+}
@@ -1,210 +1,120 @@
 use crate::parse::error::Error;
 use crate::parse::token::Token;
-use crate::reporting::Region;
+use crate::reporting::{Position, SourceFile, Span};
 
-pub type LexResult = Result<(Region, Token), Error>;
+pub type LexResult<'a> = Result<Lexeme<'a>, Error>;
 
-struct Lexer<T: Iterator<Item = char>> {
-    input: T,
-    pending: Vec<(Region, Token)>,
-    char0: Option<char>,
-    line: usize,
-    start_line: usize,
-    col: usize,
-    start_col: usize,
+/// A token together with the bytes of the input it spans.
+#[derive(Debug, PartialEq)]
+pub struct Lexeme<'a> {
+    pub span: Span,
+    pub token: Token<'a>,
 }
 
-pub fn lexer(input: &str) -> impl Iterator<Item = LexResult> + '_ {
-    Lexer::new(input.chars())
+/// A lexer, which works on slices of the input and never copies it.
+struct Lexer<'a> {
+    input: &'a str,
+    /// Byte offset of the next character.
+    offset: usize,
 }
 
-impl<T: Iterator<Item = char>> Lexer<T> {
-    /// Returns a `Lexer` based on the given iterator of characters.
-    fn new(input: T) -> Self {
-        Lexer {
-            start_line: 1,
-            start_col: 1,
-            line: 1,
-            col: 1,
-            pending: vec![],
-            input,
-            char0: None,
-        }
-    }
-
-    fn consume(&mut self) -> LexResult {
-        while self.pending.is_empty() {
-            self.consume_next()?;
-        }
-
-        Ok(self.pending.remove(0))
-    }
+pub fn lexer(input: &str) -> impl Iterator<Item = LexResult<'_>> + '_ {
+    lexer_from(input, 0)
+}
 
-    /// Consume the next [`Token`] or [`Error`].
-    fn consume_next(&mut self) -> Result<(), Error> {
-        self.start_line = self.line;
-        self.start_col = self.col;
-        match self.advance() {
-            None => self.emit(Token::Eof),
-            Some(c) => self.consume_token(c)?,
-        }
+/// Returns a lexer starting at the given byte offset of the input,
+/// which must be the start of a token or whitespace.
+pub fn lexer_from(input: &str, offset: usize) -> impl Iterator<Item = LexResult<'_>> + '_ {
+    Lexer { input, offset }
+}
 
-        Ok(())
-    }
+impl<'a> Lexer<'a> {
+    /// Consumes the next [`Token`] or [`Error`], skipping whitespace,
+    /// and returns `None` at the end of the input.
+    fn consume(&mut self) -> Option<LexResult<'a>> {
+        loop {
+            let start = self.offset;
+            let token = match self.advance()? {
+                c if c.is_whitespace() => continue,
+                '(' => Token::LParen,
+                ')' => Token::RParen,
+                '[' => Token::LBracket,
+                ']' => Token::RBracket,
+                c if c.is_ascii_digit() => match self.consume_number(start) {
+                    Ok(number) => Token::Number(number),
+                    Err(err) => return Some(Err(err)),
+                },
+                c if c.is_symbol_start() => {
+                    self.advance_while(|c| c.is_symbol());
+                    match &self.input[start..self.offset] {
+                        "true" => Token::Boolean(true),
+                        "false" => Token::Boolean(false),
+                        symbol => Token::Symbol(symbol),
+                    }
+                }
+                c => {
+                    let Position { line, col } = self.locate(start);
+                    return Some(Err(Error::BadChar(line, col, c)));
+                }
+            };
 
-    fn consume_token(&mut self, c: char) -> Result<(), Error> {
-        match c {
-            '\n' => {
-                self.line += 1;
-                self.col = 1;
-            }
-            '(' => self.emit(Token::LParen),
-            ')' => self.emit(Token::RParen),
-            c if c.is_whitespace() => {
-                // Don't need to do anything here.
-            }
-            c if c.is_ascii_digit() => self.consume_number(c)?,
-            c if c.is_symbol_start() => self.consume_symbol(c)?,
-            c => return Err(Error::BadChar(self.line, self.col - 1, c)),
+            return Some(Ok(Lexeme {
+                span: Span::new(start, self.offset),
+                token,
+            }));
         }
-
-        Ok(())
     }
 
-    fn consume_number(&mut self, start: char) -> Result<(), Error> {
-        let mut result = String::from(start);
-        while matches!(self.peek(), Some(c) if c.is_ascii_digit()) {
-            if let Some(c) = self.advance() {
-                result.push(c);
-            }
-        }
-
+    fn consume_number(&mut self, start: usize) -> Result<f64, Error> {
+        self.advance_while(|c| c.is_ascii_digit());
         if self.peek() == Some('.') {
             self.advance();
-            result.push('.');
-            while matches!(self.peek(), Some(c) if c.is_ascii_digit()) {
-                if let Some(c) = self.advance() {
-                    result.push(c);
-                }
-            }
+            self.advance_while(|c| c.is_ascii_digit());
         }
+        // Symbol characters right after the number, like in `2.5.5` or
+        // `2x`, are part of a malformed number.
+        self.advance_while(|c| c.is_symbol());
 
-        let number = result
+        self.input[start..self.offset]
             .parse::<f64>()
-            .map_err(|err| Error::Number(self.line, self.col - 1, format!("{}", err)))?;
-        self.emit(Token::Number(number));
-        Ok(())
+            .map_err(|err| {
+                let Position { line, col } = self.locate(start);
+                Error::Number(line, col, format!("{}", err))
+            })
     }
 
-    /// Consume an identifier. An identifier in Zen starts with
-    /// an alphabetic letter.
-    fn consume_symbol(&mut self, start: char) -> Result<(), Error> {
-        let mut result = String::from(start);
-        while let Some(c) = self.peek() {
-            if c.is_symbol() {
-                self.advance();
-                result.push(c);
-            } else {
-                break;
-            }
-        }
-
-        match result.as_str() {
-            // "true" => self.emit(Token::Boolean(true)),
-            // "false" => self.emit(Token::Boolean(false)),
-            str if str.len() > 1 && str.contains('/') => {
-                let vec: Vec<&str> = str.split('/').collect();
-                if let Some((a, b)) = vec.split_last() {
-                    self.emit(Token::Symbol(
-                        b.iter().map(|x| x.to_string()).collect(),
-                        a.to_string(),
-                    ))
-                }
-            }
-            str => self.emit(Token::Symbol(vec![], str.to_string())),
-        }
-
-        Ok(())
-    }
-
-    /// Pushes a new token into the [pending] list of tokens.
-    ///
-    /// ## Example
-    ///
-    /// ```ignore
-    /// let mut lexer = Lexer::new("test".chars());
-    /// assert!(lexer.pending.is_empty());
-    /// lexer.emit(Token::Data);
-    /// assert!(!lexer.pending.is_empty());
-    /// ```
-    pub fn emit(&mut self, token: Token) {
-        let region = Region::new(
-            self.start_line,
-            self.start_col,
-            self.line,
-            self.col - 1, // -1 because the col is always a character further.
-        );
-        self.pending.push((region, token));
+    /// Returns the line and column of a byte offset, which is only
+    /// needed for errors.
+    fn locate(&self, offset: usize) -> Position {
+        SourceFile::new(None, self.input).position(offset)
     }
 
     /// Returns the next character in the input, without advancing
     /// the input.
-    ///
-    /// This allows to look ahead to decide what to do, based on
-    /// the character.
-    ///
-    /// # Example
-    ///
-    /// ```ignore
-    /// let lexer = Lexer::new("test".chars());
-    /// assert_eq!(lexer.peek(), Some('t'));
-    /// ```
-    pub fn peek(&mut self) -> Option<char> {
-        if self.char0.is_none() {
-            self.char0 = self.input.next();
-        }
+    fn peek(&self) -> Option<char> {
+        self.input[self.offset..].chars().next()
+    }
 
-        self.char0
+    /// Returns the next character in the input and moves past it.
+    fn advance(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.offset += c.len_utf8();
+        Some(c)
     }
 
-    /// Returns the next character in the input.
-    ///
-    /// If [peek](peek) has been called before, [advance](advance)
-    /// will return the peeked character instead of advancing.
-    ///
-    /// # Example
-    ///
-    /// ```ignore
-    /// let lexer = Lexer::new("test".chars());
-    /// assert_eq!(lexer.advance(), Some('t'));
-    /// ```
-    ///
-    /// ```ignore
-    /// let lexer = Lexer::new("test".chars());
-    /// assert_eq!(lexer.peek(), Some('t'));
-    /// assert_eq!(lexer.advance(), Some('t'));
-    /// ```
-    pub fn advance(&mut self) -> Option<char> {
-        self.col += 1;
-        match self.char0 {
-            None => self.input.next(),
-            Some(c) => {
-                self.char0 = None;
-                Some(c)
-            }
+    /// Advances as long as the next character matches `predicate`.
+    fn advance_while(&mut self, predicate: impl Fn(char) -> bool) {
+        while matches!(self.peek(), Some(c) if predicate(c)) {
+            self.advance();
         }
     }
 }
 
-impl<T: Iterator<Item = char>> Iterator for Lexer<T> {
-    type Item = LexResult;
+impl<'a> Iterator for Lexer<'a> {
+    type Item = LexResult<'a>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        match self.consume() {
-            Ok((_, Token::Eof)) => None,
-            Ok(token) => Some(Ok(token)),
-            Err(err) => Some(Err(err)),
-        }
+        self.consume()
     }
 }
 
@@ -240,14 +150,16 @@ impl SymbolExt for char {
             || *self == ':'
             || *self == '#'
             || *self == '+'
+            || *self == '^'
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use crate::parse::error::Error;
     use crate::parse::lexer::lexer;
     use crate::parse::token::Token;
-    use crate::reporting::Region;
+    use crate::reporting::{Region, SourceFile, Span};
     use pretty_assertions::assert_eq;
 
     #[test]
@@ -256,8 +168,8 @@ mod tests {
 
         let expected: Vec<(Region, Token)> = vec![
             ((1, 1).into(), Token::LParen),
-            ((1, 2, 4).into(), Token::Symbol(vec![], "def".to_string())),
-            ((1, 6, 8).into(), Token::Symbol(vec![], "foo".to_string())),
+            ((1, 2, 4).into(), Token::Symbol("def")),
+            ((1, 6, 8).into(), Token::Symbol("foo")),
             ((1, 10).into(), Token::Number(5.0)),
             ((1, 11).into(), Token::RParen),
         ];
@@ -265,7 +177,53 @@ mod tests {
         assert_eq!(expected, results)
     }
 
-    fn lex(input: &str) -> Vec<(Region, Token)> {
-        lexer(input).filter_map(Result::ok).collect()
+    #[test]
+    pub fn lex_byte_offsets() {
+        let offsets: Vec<(Span, Token)> = lexer("(ä\n  1.5 x/y)")
+            .filter_map(Result::ok)
+            .map(|lexeme| (lexeme.span, lexeme.token))
+            .collect();
+
+        let expected = vec![
+            (Span::new(0, 1), Token::LParen),
+            (Span::new(1, 3), Token::Symbol("ä")),
+            (Span::new(6, 9), Token::Number(1.5)),
+            (Span::new(10, 13), Token::Symbol("x/y")),
+            (Span::new(13, 14), Token::RParen),
+        ];
+        assert_eq!(expected, offsets);
+    }
+
+    #[test]
+    pub fn lex_booleans() {
+        let results = lex("(not true false?)");
+
+        let expected: Vec<(Region, Token)> = vec![
+            ((1, 1).into(), Token::LParen),
+            ((1, 2, 4).into(), Token::Symbol("not")),
+            ((1, 6, 9).into(), Token::Boolean(true)),
+            ((1, 11, 16).into(), Token::Symbol("false?")),
+            ((1, 17).into(), Token::RParen),
+        ];
+
+        assert_eq!(expected, results)
+    }
+
+    #[test]
+    pub fn lex_malformed_numbers() {
+        let results: Vec<_> = lexer("(+ 1 2.5.5)").collect();
+        assert!(matches!(results[3], Err(Error::Number(1, 6, _))));
+        assert!(matches!(
+            lexer("2x").next(),
+            Some(Err(Error::Number(1, 1, _)))
+        ));
+    }
+
+    fn lex(input: &str) -> Vec<(Region, Token<'_>)> {
+        let file = SourceFile::new(None, input);
+        lexer(input)
+            .filter_map(Result::ok)
+            .map(|lexeme| (file.region(lexeme.span), lexeme.token))
+            .collect()
     }
 }
@@ -1,10 +1,15 @@
-use serde::{Deserialize, Serialize};
+use serde::Serialize;
 
-#[derive(Debug, PartialEq, Serialize, Deserialize)]
-pub enum Token {
+/// A token, which borrows its text from the input.
+#[derive(Debug, PartialEq, Serialize)]
+pub enum Token<'a> {
     LParen,
     RParen,
-    Symbol(Vec<String>, String),
+    LBracket,
+    RBracket,
+    /// A symbol including its namespace, e.g. `my.util/double`.
+    Symbol(&'a str),
     Number(f64),
-    Eof,
+    /// `true` or `false`.
+    Boolean(bool),
 }
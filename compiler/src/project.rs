@@ -0,0 +1,377 @@
+pub mod error;
+
+use crate::parse;
+use crate::parse::{Expr, Module};
+use crate::reporting::Region;
+use error::Error;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// The namespace of modules without an `ns` declaration.
+pub const DEFAULT_NAMESPACE: &str = "user";
+
+/// A module together with its `ns` declaration.
+#[derive(Debug)]
+pub struct Namespace {
+    pub name: String,
+    pub requires: Vec<Require>,
+    pub module: Module,
+}
+
+/// A required namespace, i.e. `[my.util :as u]`.
+#[derive(Debug)]
+pub struct Require {
+    pub region: Region,
+    pub namespace: String,
+    pub alias: Option<String>,
+}
+
+impl Namespace {
+    /// Returns the namespace declared by the first expression of the
+    /// given module in the form of `(ns name (:require ...))`.
+    pub fn new(module: Module) -> Result<Namespace, Error> {
+        let declaration = match module.expressions.first() {
+            Some(expr) if is_ns(expr) => expr,
+            _ => {
+                return Ok(Namespace {
+                    name: DEFAULT_NAMESPACE.to_string(),
+                    requires: vec![],
+                    module,
+                })
+            }
+        };
+
+        let bad_namespace = |region: &Region| Error::BadNamespace(module.filename.clone(), *region);
+        let (name, clauses) = match declaration {
+            Expr::List { expressions, .. } => match expressions.as_slice() {
+                [_, Expr::Symbol {
+                    namespace, value, ..
+                }, clauses @ ..]
+                    if namespace.is_empty() =>
+                {
+                    (value.clone(), clauses)
+                }
+                _ => return Err(bad_namespace(declaration.region())),
+            },
+            _ => return Err(bad_namespace(declaration.region())),
+        };
+
+        let mut requires = vec![];
+        for clause in clauses {
+            match clause {
+                Expr::List { expressions, .. } => match expressions.as_slice() {
+                    [Expr::Symbol { value, .. }, specs @ ..] if value == ":require" => {
+                        for spec in specs {
+                            let require =
+                                Require::new(spec).ok_or_else(|| bad_namespace(spec.region()))?;
+                            requires.push(require);
+                        }
+                    }
+                    _ => return Err(bad_namespace(clause.region())),
+                },
+                _ => return Err(bad_namespace(clause.region())),
+            }
+        }
+
+        Ok(Namespace {
+            name,
+            requires,
+            module,
+        })
+    }
+
+    /// Returns the namespaces, which may be used as a qualifier in
+    /// this module, mapped to the namespace they refer to.
+    pub fn aliases(&self) -> HashMap<String, String> {
+        let mut aliases = HashMap::new();
+        aliases.insert(self.name.clone(), self.name.clone());
+        for require in &self.requires {
+            aliases.insert(require.namespace.clone(), require.namespace.clone());
+            if let Some(alias) = &require.alias {
+                aliases.insert(alias.clone(), require.namespace.clone());
+            }
+        }
+        aliases
+    }
+}
+
+impl Require {
+    /// Returns the required namespace of either `my.util` or
+    /// `[my.util :as u]`.
+    fn new(spec: &Expr) -> Option<Require> {
+        match spec {
+            Expr::Symbol {
+                region,
+                namespace,
+                value,
+            } if namespace.is_empty() => Some(Require {
+                region: *region,
+                namespace: value.clone(),
+                alias: None,
+            }),
+            Expr::Vector { expressions, .. } => match expressions.as_slice() {
+                [Expr::Symbol {
+                    region,
+                    namespace,
+                    value,
+                }] if namespace.is_empty() => Some(Require {
+                    region: *region,
+                    namespace: value.clone(),
+                    alias: None,
+                }),
+                [Expr::Symbol {
+                    region,
+                    namespace,
+                    value,
+                }, Expr::Symbol { value: keyword, .. }, Expr::Symbol { value: alias, .. }]
+                    if namespace.is_empty() && keyword == ":as" =>
+                {
+                    Some(Require {
+                        region: *region,
+                        namespace: value.clone(),
+                        alias: Some(alias.clone()),
+                    })
+                }
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+}
+
+/// Returns whether the given expression is an `ns` declaration.
+pub fn is_ns(expr: &Expr) -> bool {
+    match expr {
+        Expr::List { expressions, .. } => matches!(
+            expressions.first(),
+            Some(Expr::Symbol { namespace, value, .. }) if namespace.is_empty() && value == "ns"
+        ),
+        _ => false,
+    }
+}
+
+/// Finds the source of a namespace.
+pub trait Loader {
+    /// Returns the filename and source of the given namespace, if it
+    /// exists.
+    fn load(&self, namespace: &str) -> Option<(String, String)>;
+}
+
+/// Loads namespaces from files relative to a list of root directories,
+/// where `my.util` is located in `<root>/my/util.edn` of the first root
+/// containing it.
+pub struct FileLoader {
+    roots: Vec<PathBuf>,
+}
+
+impl FileLoader {
+    pub fn new(roots: Vec<PathBuf>) -> Self {
+        FileLoader { roots }
+    }
+
+    /// Returns the loader for the project `path` is part of, given it
+    /// declares the given namespace.
+    pub fn for_file(path: &Path, namespace: &str) -> Self {
+        let mut root = path.parent().unwrap_or(Path::new("")).to_path_buf();
+        for _ in namespace.matches('.') {
+            root.pop();
+        }
+        FileLoader::new(vec![root])
+    }
+
+    /// Returns all namespaces found in the root directories.
+    pub fn namespaces(&self) -> Vec<String> {
+        let mut namespaces = vec![];
+        for root in &self.roots {
+            collect_namespaces(root, &mut vec![], &mut namespaces);
+        }
+        namespaces.sort();
+        namespaces.dedup();
+        namespaces
+    }
+
+    /// Returns the path of the given namespace relative to `root`.
+    pub fn path(root: &Path, namespace: &str) -> PathBuf {
+        let mut path = root.to_path_buf();
+        path.extend(namespace.split('.'));
+        path.set_extension("edn");
+        path
+    }
+}
+
+impl Loader for FileLoader {
+    fn load(&self, namespace: &str) -> Option<(String, String)> {
+        self.roots.iter().find_map(|root| {
+            let path = FileLoader::path(root, namespace);
+            let source = fs::read_to_string(&path).ok()?;
+            Some((path.to_string_lossy().to_string(), source))
+        })
+    }
+}
+
+fn collect_namespaces(dir: &Path, prefix: &mut Vec<String>, namespaces: &mut Vec<String>) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let Some(name) = path.file_stem().and_then(|name| name.to_str()) else {
+            continue;
+        };
+        if path.is_dir() {
+            prefix.push(name.to_string());
+            collect_namespaces(&path, prefix, namespaces);
+            prefix.pop();
+        } else if path.extension().is_some_and(|extension| extension == "edn") {
+            let mut namespace = prefix.clone();
+            namespace.push(name.to_string());
+            namespaces.push(namespace.join("."));
+        }
+    }
+}
+
+/// Loads namespaces from memory, mapping each namespace to its source.
+impl Loader for HashMap<String, String> {
+    fn load(&self, namespace: &str) -> Option<(String, String)> {
+        self.get(namespace)
+            .map(|source| (namespace.to_string(), source.clone()))
+    }
+}
+
+/// Loads the file at the given path and all namespaces it requires.
+pub fn load_file(path: &Path) -> Result<Vec<Namespace>, Error> {
+    let filename = path.to_str().map(|x| x.to_string());
+    let source =
+        fs::read_to_string(path).map_err(|err| Error::Io(filename.clone(), err.to_string()))?;
+    let module =
+        parse::parse(filename.clone(), &source).map_err(|err| Error::Parse(filename, err))?;
+    let entry = Namespace::new(module)?;
+    let loader = FileLoader::for_file(path, &entry.name);
+    load(entry, &loader)
+}
+
+/// Loads the given namespaces and all namespaces they require in
+/// dependency order, see [`load`].
+pub fn load_entries<L: Loader>(entries: &[&str], loader: &L) -> Result<Vec<Namespace>, Error> {
+    let mut loaded = vec![];
+    for entry in entries {
+        if loaded.iter().any(|ns: &Namespace| ns.name == *entry) {
+            continue;
+        }
+
+        let (filename, source) = loader
+            .load(entry)
+            .ok_or_else(|| Error::MissingEntry(entry.to_string()))?;
+        let module = parse::parse(Some(filename.clone()), &source)
+            .map_err(|err| Error::Parse(Some(filename), err))?;
+        visit(Namespace::new(module)?, loader, &mut vec![], &mut loaded)?;
+    }
+    Ok(loaded)
+}
+
+/// Loads all namespaces required by `entry`, directly or indirectly.
+///
+/// The namespaces are returned in dependency order, so every namespace
+/// comes after the namespaces it requires and `entry` comes last.
+pub fn load<L: Loader>(entry: Namespace, loader: &L) -> Result<Vec<Namespace>, Error> {
+    let mut loaded = vec![];
+    visit(entry, loader, &mut vec![], &mut loaded)?;
+    Ok(loaded)
+}
+
+fn visit<L: Loader>(
+    namespace: Namespace,
+    loader: &L,
+    visiting: &mut Vec<String>,
+    loaded: &mut Vec<Namespace>,
+) -> Result<(), Error> {
+    visiting.push(namespace.name.clone());
+    for require in &namespace.requires {
+        if loaded.iter().any(|ns| ns.name == require.namespace) {
+            continue;
+        }
+
+        let filename = &namespace.module.filename;
+        if let Some(start) = visiting.iter().position(|ns| *ns == require.namespace) {
+            let mut cycle = visiting[start..].to_vec();
+            cycle.push(require.namespace.clone());
+            return Err(Error::Cycle(filename.clone(), require.region, cycle));
+        }
+
+        let (filename, source) = loader.load(&require.namespace).ok_or_else(|| {
+            Error::NotFound(filename.clone(), require.region, require.namespace.clone())
+        })?;
+        let module = parse::parse(Some(filename.clone()), &source)
+            .map_err(|err| Error::Parse(Some(filename), err))?;
+        visit(Namespace::new(module)?, loader, visiting, loaded)?;
+    }
+    visiting.pop();
+    loaded.push(namespace);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parse::parse;
+    use crate::project::error::Error;
+    use crate::project::{load, Namespace};
+    use std::collections::HashMap;
+
+    #[test]
+    fn load_in_dependency_order() {
+        let sources = sources(&[
+            ("my.util", "(ns my.util (:require my.math))"),
+            ("my.math", "(ns my.math)"),
+        ]);
+        let entry = namespace("(ns my.app (:require [my.util :as u] [my.math]))");
+
+        let loaded = load(entry, &sources).unwrap();
+        let names: Vec<&str> = loaded.iter().map(|ns| ns.name.as_str()).collect();
+
+        assert_eq!(vec!["my.math", "my.util", "my.app"], names);
+        assert_eq!(Some(&"my.util".to_string()), loaded[2].aliases().get("u"));
+    }
+
+    #[test]
+    fn load_detects_cycles() {
+        let sources = sources(&[
+            ("my.a", "(ns my.a (:require my.b))"),
+            ("my.b", "(ns my.b\n  (:require my.a))"),
+        ]);
+        let entry = namespace("(ns my.app (:require my.a))");
+
+        match load(entry, &sources) {
+            Err(Error::Cycle(filename, region, cycle)) => {
+                assert_eq!(Some("my.b".to_string()), filename);
+                assert_eq!(
+                    (2, 13, 16),
+                    (region.start.line, region.start.col, region.end.col)
+                );
+                assert_eq!(vec!["my.a", "my.b", "my.a"], cycle);
+            }
+            result => panic!("Expected a cycle, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn load_reports_missing_namespaces() {
+        let entry = namespace("(ns my.app (:require [my.missing :as m]))");
+
+        assert!(matches!(
+            load(entry, &HashMap::new()),
+            Err(Error::NotFound(_, _, namespace)) if namespace == "my.missing"
+        ));
+    }
+
+    fn namespace(source: &str) -> Namespace {
+        Namespace::new(parse(None, source).unwrap()).unwrap()
+    }
+
+    fn sources(sources: &[(&str, &str)]) -> HashMap<String, String> {
+        sources
+            .iter()
+            .map(|(name, source)| (name.to_string(), source.to_string()))
+            .collect()
+    }
+}
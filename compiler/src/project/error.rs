@@ -0,0 +1,151 @@
+use crate::parse;
+use crate::reporting::{code, Diagnostic, Region};
+use serde::{Deserialize, Serialize};
+
+/// An error while loading the modules of a project, each with the
+/// filename of the module it occurred in.
+#[derive(Debug, Serialize, Deserialize)]
+pub enum Error {
+    Io(Option<String>, String),
+    Parse(Option<String>, parse::error::Error),
+    BadNamespace(Option<String>, Region),
+    NotFound(Option<String>, Region, String),
+    Cycle(Option<String>, Region, Vec<String>),
+    MissingEntry(String),
+    UnknownExport(String),
+    /// The entry namespace defines `main` and has top-level expressions,
+    /// which are exported as `main` as well.
+    ///
+    /// Contains the region of the definition and the region of the first
+    /// top-level expression of the entry namespace, if it has any.
+    DuplicateMain(Option<String>, Region, Option<Region>),
+    /// A symbol does not refer to anything.
+    ///
+    /// Contains the symbol and the name it most likely is a typo of, if
+    /// any.
+    Unbound(Option<String>, Region, String, Option<String>),
+    /// A special form or declaration does not have the expected shape,
+    /// which the message describes.
+    BadForm(Option<String>, Region, String),
+    /// A function is defined more than once in the same namespace,
+    /// which is reported as a malformed form.
+    ///
+    /// Contains the name of the function and the region of its first
+    /// definition.
+    Duplicate(Option<String>, Region, String, Region),
+    /// A function is called with the wrong number of arguments.
+    ///
+    /// Contains the name of the function, its arity and the number of
+    /// arguments.
+    Arity(Option<String>, Region, String, usize, usize),
+    /// The compiled module is invalid, which is a bug in the compiler.
+    ///
+    /// Contains the filename and region of the function the module is
+    /// invalid in, if known, its name and the reason.
+    CompilerBug(Option<String>, Option<Region>, String, String),
+}
+
+impl Error {
+    /// Returns the diagnostic for this error.
+    pub fn to_diagnostic(&self) -> Diagnostic {
+        match self {
+            Error::Io(filename, reason) => {
+                Diagnostic::error(code::IO, format!("cannot read file: {}", reason))
+                    .in_file(filename.clone())
+            }
+            Error::Parse(filename, error) => error.to_diagnostic(filename.clone()),
+            Error::BadNamespace(filename, region) => {
+                Diagnostic::error(code::BAD_NAMESPACE, "malformed namespace declaration")
+                    .in_file(filename.clone())
+                    .at(*region)
+                    .with_note("a namespace is declared as `(ns name (:require other ...))`")
+            }
+            Error::NotFound(filename, region, namespace) => Diagnostic::error(
+                code::NAMESPACE_NOT_FOUND,
+                format!("cannot find namespace `{}`", namespace),
+            )
+            .in_file(filename.clone())
+            .at(*region),
+            Error::Cycle(filename, region, cycle) => Diagnostic::error(
+                code::REQUIRE_CYCLE,
+                format!("namespace `{}` requires itself", cycle[0]),
+            )
+            .in_file(filename.clone())
+            .at(*region)
+            .with_note(format!("the cycle is {}", cycle.join(" -> "))),
+            Error::MissingEntry(namespace) => Diagnostic::error(
+                code::MISSING_ENTRY,
+                format!("cannot find the entry namespace `{}`", namespace),
+            ),
+            Error::UnknownExport(name) => Diagnostic::error(
+                code::UNKNOWN_EXPORT,
+                format!("cannot export `{}`, which is not defined", name),
+            ),
+            Error::DuplicateMain(filename, region, script) => {
+                let diagnostic = Diagnostic::error(
+                    code::DUPLICATE_MAIN,
+                    "`main` is defined, but there are top-level expressions as well",
+                )
+                .in_file(filename.clone())
+                .at(*region)
+                .with_note("top-level expressions are exported as `main`");
+                match script {
+                    Some(script) => {
+                        diagnostic.with_label(*script, "the first top-level expression")
+                    }
+                    None => diagnostic,
+                }
+            }
+            Error::Unbound(filename, region, name, suggestion) => {
+                let diagnostic =
+                    Diagnostic::error(code::UNBOUND_SYMBOL, format!("cannot find `{}`", name))
+                        .in_file(filename.clone())
+                        .at(*region);
+                match suggestion {
+                    Some(suggestion) => diagnostic.with_suggestion(
+                        "a function with a similar name exists",
+                        *region,
+                        suggestion,
+                    ),
+                    None => diagnostic,
+                }
+            }
+            Error::BadForm(filename, region, message) => Diagnostic::error(code::BAD_FORM, message)
+                .in_file(filename.clone())
+                .at(*region),
+            Error::Duplicate(filename, region, name, first) => {
+                Diagnostic::error(code::BAD_FORM, format!("`{}` is already defined", name))
+                    .in_file(filename.clone())
+                    .at(*region)
+                    .with_label(*first, format!("`{}` is first defined here", name))
+            }
+            Error::Arity(filename, region, name, arity, args) => Diagnostic::error(
+                code::ARITY,
+                format!(
+                    "`{}` takes {} {}, but is called with {}",
+                    name,
+                    arity,
+                    if *arity == 1 { "argument" } else { "arguments" },
+                    args
+                ),
+            )
+            .in_file(filename.clone())
+            .at(*region),
+            Error::CompilerBug(filename, region, function, reason) => {
+                let diagnostic = Diagnostic::error(
+                    code::COMPILER_BUG,
+                    format!(
+                        "compiled `{}` into invalid WebAssembly: {}",
+                        function, reason
+                    ),
+                )
+                .in_file(filename.clone())
+                .with_note("this is a bug in the compiler");
+                match region {
+                    Some(region) => diagnostic.at(*region),
+                    None => diagnostic,
+                }
+            }
+        }
+    }
+}
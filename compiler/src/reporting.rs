@@ -1,17 +1,24 @@
+pub mod code;
+mod diagnostic;
+mod source;
+
 use serde::ser::SerializeSeq;
 use serde::{Deserialize, Serialize, Serializer};
 
+pub use diagnostic::{Diagnostic, Label, Severity, Suggestion};
+pub use source::{Encoding, Locator, SourceFile, Span};
+
 pub type Line = usize;
 
 pub type Col = usize;
 
-#[derive(Debug, Eq, Ord, PartialOrd, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, Eq, Ord, PartialOrd, PartialEq, Serialize, Deserialize)]
 pub struct Position {
     pub line: Line,
     pub col: Col,
 }
 
-#[derive(Debug, Eq, Ord, PartialOrd, PartialEq, Deserialize)]
+#[derive(Debug, Clone, Copy, Eq, Ord, PartialOrd, PartialEq, Deserialize)]
 pub struct Region {
     pub start: Position,
     pub end: Position,
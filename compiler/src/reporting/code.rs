@@ -0,0 +1,71 @@
+/// A stable identifier of a kind of diagnostic, which has a long-form
+/// explanation shown by `wasp explain`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Code {
+    pub id: &'static str,
+    pub explanation: &'static str,
+}
+
+macro_rules! codes {
+    ($($name:ident = $id:literal,)*) => {
+        $(pub const $name: Code = Code {
+            id: $id,
+            explanation: include_str!(concat!("explanations/", $id, ".md")),
+        };)*
+
+        /// All codes, ordered by their id.
+        pub const CODES: &[Code] = &[$($name),*];
+    };
+}
+
+codes! {
+    BAD_CHAR = "W0001",
+    BAD_NUMBER = "W0002",
+    BAD_END_OF_INPUT = "W0003",
+    IO = "W0004",
+    BAD_NAMESPACE = "W0005",
+    NAMESPACE_NOT_FOUND = "W0006",
+    REQUIRE_CYCLE = "W0007",
+    MISSING_ENTRY = "W0008",
+    UNKNOWN_EXPORT = "W0009",
+    DUPLICATE_MAIN = "W0010",
+    UNBOUND_SYMBOL = "W0011",
+    BAD_FORM = "W0012",
+    ARITY = "W0013",
+    COMPILER_BUG = "W0014",
+}
+
+/// Returns the code with the given id, which is case-insensitive.
+pub fn lookup(id: &str) -> Option<Code> {
+    CODES
+        .iter()
+        .find(|code| code.id.eq_ignore_ascii_case(id))
+        .copied()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::compile::compile;
+    use crate::reporting::code::{
+        lookup, Code, BAD_CHAR, BAD_END_OF_INPUT, BAD_NUMBER, CODES, UNBOUND_SYMBOL,
+    };
+
+    #[test]
+    fn look_up_codes() {
+        assert_eq!(Some(UNBOUND_SYMBOL), lookup("w0011"));
+        assert_eq!(None, lookup("W9999"));
+        for (idx, code) in CODES.iter().enumerate() {
+            assert_eq!(format!("W{:04}", idx + 1), code.id);
+            assert!(!code.explanation.is_empty());
+        }
+    }
+
+    #[test]
+    fn examples_report_their_code() {
+        let first_example = |code: Code| code.explanation.split("```").nth(1).unwrap();
+        for code in [BAD_CHAR, BAD_NUMBER, BAD_END_OF_INPUT] {
+            let error = compile(None, first_example(code)).unwrap_err();
+            assert_eq!(code.id, error.to_diagnostic().code);
+        }
+    }
+}
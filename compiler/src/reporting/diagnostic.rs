@@ -0,0 +1,211 @@
+use crate::reporting::code::Code;
+use crate::reporting::Region;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// A problem the compiler reports about a program, which every phase
+/// produces and which is serialized the same way for the CLI and the
+/// browser.
+///
+/// ```json
+/// {
+///   "severity": "error",
+///   "code": "W0011",
+///   "message": "cannot find `lenght`",
+///   "filename": "main.edn",
+///   "region": [2, 4, 2, 9],
+///   "labels": [],
+///   "notes": [],
+///   "suggestions": [{ "message": "a function with a similar name exists", "region": [2, 4, 2, 9], "replacement": "length" }]
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    /// Identifies the kind of the diagnostic, independent of its
+    /// message, see [`Code`].
+    pub code: String,
+    pub message: String,
+    pub filename: Option<String>,
+    /// Where the problem is, if it can be pinned to the source.
+    pub region: Option<Region>,
+    /// Further regions related to the problem.
+    pub labels: Vec<Label>,
+    pub notes: Vec<String>,
+    pub suggestions: Vec<Suggestion>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+/// A region of the source with a message explaining its part in a
+/// diagnostic.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Label {
+    pub region: Region,
+    pub message: String,
+}
+
+/// A fix, which replaces the source in `region` with `replacement`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Suggestion {
+    pub message: String,
+    pub region: Region,
+    pub replacement: String,
+}
+
+impl Diagnostic {
+    /// Returns a new error without a location, labels, notes or
+    /// suggestions.
+    pub fn error(code: Code, message: impl Into<String>) -> Self {
+        Diagnostic {
+            severity: Severity::Error,
+            code: code.id.to_string(),
+            message: message.into(),
+            filename: None,
+            region: None,
+            labels: vec![],
+            notes: vec![],
+            suggestions: vec![],
+        }
+    }
+
+    pub fn in_file(mut self, filename: Option<String>) -> Self {
+        self.filename = filename;
+        self
+    }
+
+    pub fn at(mut self, region: Region) -> Self {
+        self.region = Some(region);
+        self
+    }
+
+    pub fn with_label(mut self, region: Region, message: impl Into<String>) -> Self {
+        self.labels.push(Label {
+            region,
+            message: message.into(),
+        });
+        self
+    }
+
+    pub fn with_note(mut self, note: impl Into<String>) -> Self {
+        self.notes.push(note.into());
+        self
+    }
+
+    pub fn with_suggestion(
+        mut self,
+        message: impl Into<String>,
+        region: Region,
+        replacement: impl Into<String>,
+    ) -> Self {
+        self.suggestions.push(Suggestion {
+            message: message.into(),
+            region,
+            replacement: replacement.into(),
+        });
+        self
+    }
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+        }
+    }
+}
+
+/// Renders the diagnostic for humans, for example:
+///
+/// ```text
+/// error[W0011]: cannot find `lenght`
+///   --> main.edn:2:4
+///   = help: a function with a similar name exists: `length`
+/// ```
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}[{}]: {}", self.severity, self.code, self.message)?;
+        let filename = self.filename.as_deref().unwrap_or("<unknown>");
+        match self.region {
+            Some(region) => write!(
+                f,
+                "\n  --> {}:{}:{}",
+                filename, region.start.line, region.start.col
+            )?,
+            None if self.filename.is_some() => write!(f, "\n  --> {}", filename)?,
+            None => {}
+        }
+        for label in &self.labels {
+            write!(
+                f,
+                "\n  {}:{}:{}: {}",
+                filename, label.region.start.line, label.region.start.col, label.message
+            )?;
+        }
+        for note in &self.notes {
+            write!(f, "\n  = note: {}", note)?;
+        }
+        for suggestion in &self.suggestions {
+            write!(
+                f,
+                "\n  = help: {}: `{}`",
+                suggestion.message, suggestion.replacement
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::project::error::Error;
+    use crate::reporting::Region;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn serialize_and_render_diagnostics() {
+        let error = Error::Unbound(
+            Some("main.edn".to_string()),
+            Region::from((2, 4, 9)),
+            "lenght".to_string(),
+            Some("length".to_string()),
+        );
+        let diagnostic = error.to_diagnostic();
+
+        assert_eq!(
+            serde_json::json!({
+                "severity": "error",
+                "code": "W0011",
+                "message": "cannot find `lenght`",
+                "filename": "main.edn",
+                "region": [2, 4, 2, 9],
+                "labels": [],
+                "notes": [],
+                "suggestions": [{
+                    "message": "a function with a similar name exists",
+                    "region": [2, 4, 2, 9],
+                    "replacement": "length",
+                }],
+            }),
+            serde_json::to_value(&diagnostic).unwrap()
+        );
+        assert_eq!(
+            "error[W0011]: cannot find `lenght`\n  --> main.edn:2:4\n  = help: a function with a similar name exists: `length`",
+            diagnostic.to_string()
+        );
+
+        let error = Error::UnknownExport("missing".to_string());
+        let diagnostic = error.to_diagnostic();
+        assert_eq!(None, diagnostic.region);
+        assert_eq!(
+            "error[W0009]: cannot export `missing`, which is not defined",
+            diagnostic.to_string()
+        );
+    }
+}
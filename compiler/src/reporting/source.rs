@@ -0,0 +1,216 @@
+//! Source files, which convert byte offsets into lines and columns.
+
+use crate::reporting::{Col, Line, Position, Region};
+use serde::{Deserialize, Serialize};
+
+/// A range of bytes in a source file, where `end` is exclusive.
+#[derive(Debug, Clone, Copy, Default, Eq, Ord, PartialOrd, PartialEq, Serialize, Deserialize)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Self {
+        Span { start, end }
+    }
+
+    /// Returns the span from the start of `self` to the end of `other`.
+    pub fn to(self, other: Span) -> Span {
+        Span::new(self.start, other.end)
+    }
+
+    pub fn len(&self) -> usize {
+        self.end - self.start
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.start == self.end
+    }
+}
+
+/// How columns are counted.
+///
+/// [`Region`]s count characters, editors speaking the language server
+/// protocol and JavaScript count UTF-16 code units.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    Utf8,
+    Utf16,
+    Utf32,
+}
+
+/// The contents of a source file together with the offsets of its lines.
+#[derive(Debug, Clone)]
+pub struct SourceFile {
+    pub name: Option<String>,
+    source: String,
+    /// Byte offset of the start of each line.
+    lines: Vec<usize>,
+}
+
+impl SourceFile {
+    pub fn new(name: Option<String>, source: impl Into<String>) -> Self {
+        let source = source.into();
+        let lines = std::iter::once(0)
+            .chain(source.match_indices('\n').map(|(offset, _)| offset + 1))
+            .collect();
+        SourceFile {
+            name,
+            source,
+            lines,
+        }
+    }
+
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    /// Returns the 1-based line of the given byte offset.
+    pub fn line(&self, offset: usize) -> Line {
+        self.lines.partition_point(|start| *start <= offset)
+    }
+
+    /// Returns the 1-based line and column in characters of the given
+    /// byte offset.
+    pub fn position(&self, offset: usize) -> Position {
+        self.position_in(offset, Encoding::Utf32)
+    }
+
+    /// Returns the 1-based line and column of the given byte offset,
+    /// where the column is counted in the given encoding.
+    pub fn position_in(&self, offset: usize, encoding: Encoding) -> Position {
+        let line = self.line(offset);
+        let prefix = &self.source[self.lines[line - 1]..offset];
+        let col: Col = match encoding {
+            Encoding::Utf8 => prefix.len(),
+            Encoding::Utf16 => prefix.encode_utf16().count(),
+            Encoding::Utf32 => prefix.chars().count(),
+        };
+        Position { line, col: col + 1 }
+    }
+
+    /// Returns the byte offset of a 1-based line and column in
+    /// characters, if it is part of the file.
+    pub fn offset(&self, position: Position) -> Option<usize> {
+        let start = *self.lines.get(position.line.checked_sub(1)?)?;
+        let end = match self.lines.get(position.line) {
+            Some(next) => next - 1,
+            None => self.source.len(),
+        };
+        let line = &self.source[start..end];
+        line.char_indices()
+            .map(|(offset, _)| offset)
+            .chain(std::iter::once(line.len()))
+            .nth(position.col.checked_sub(1)?)
+            .map(|offset| start + offset)
+    }
+
+    /// Returns the region of the given span, which ends at its last
+    /// character.
+    pub fn region(&self, span: Span) -> Region {
+        self.locator().region(span)
+    }
+
+    /// Returns a [`Locator`] starting at the beginning of the file.
+    pub fn locator(&self) -> Locator<'_> {
+        Locator {
+            file: self,
+            line: 1,
+            offset: 0,
+            col: 1,
+        }
+    }
+
+    /// Returns the byte offset of the last character of the span, or its
+    /// start, if it is empty.
+    fn last(&self, span: Span) -> usize {
+        match self.source[..span.end].char_indices().next_back() {
+            Some((last, _)) if span.end > span.start => last,
+            _ => span.start,
+        }
+    }
+}
+
+/// Converts byte offsets into positions like [`SourceFile::position`],
+/// but only counts the characters since the previous offset, if the
+/// offsets are increasing, e.g. while parsing.
+pub struct Locator<'a> {
+    file: &'a SourceFile,
+    line: Line,
+    /// The previous offset and its column.
+    offset: usize,
+    col: Col,
+}
+
+impl<'a> Locator<'a> {
+    pub fn position(&mut self, offset: usize) -> Position {
+        let lines = &self.file.lines;
+        let backwards = offset < self.offset;
+        if backwards || lines.get(self.line).is_some_and(|next| *next <= offset) {
+            // Moving to the next line is the common case.
+            let next_line = lines.get(self.line + 1).is_none_or(|after| *after > offset);
+            self.line = if !backwards && next_line {
+                self.line + 1
+            } else {
+                self.file.line(offset)
+            };
+            self.offset = lines[self.line - 1];
+            self.col = 1;
+        }
+
+        self.col += self.file.source[self.offset..offset].chars().count();
+        self.offset = offset;
+        Position {
+            line: self.line,
+            col: self.col,
+        }
+    }
+
+    /// See [`SourceFile::region`].
+    pub fn region(&mut self, span: Span) -> Region {
+        let start = self.position(span.start);
+        let end = self.position(self.file.last(span));
+        Region { start, end }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::reporting::source::{Encoding, SourceFile, Span};
+    use crate::reporting::{Position, Region};
+
+    #[test]
+    fn convert_offsets_to_positions() {
+        // "ä" takes two bytes, "𝄞" four bytes and two UTF-16 code units.
+        let file = SourceFile::new(None, "(ä\n  (𝄞 x))");
+
+        assert_eq!(Position { line: 1, col: 3 }, file.position(3));
+        assert_eq!(Position { line: 2, col: 1 }, file.position(4));
+        assert_eq!(Position { line: 2, col: 6 }, file.position(12));
+        assert_eq!(
+            Position { line: 2, col: 9 },
+            file.position_in(12, Encoding::Utf8)
+        );
+        assert_eq!(
+            Position { line: 2, col: 7 },
+            file.position_in(12, Encoding::Utf16)
+        );
+
+        assert_eq!(Some(12), file.offset(Position { line: 2, col: 6 }));
+        assert_eq!(Some(3), file.offset(Position { line: 1, col: 3 }));
+        assert_eq!(None, file.offset(Position { line: 1, col: 4 }));
+        assert_eq!(Some(15), file.offset(Position { line: 2, col: 9 }));
+        assert_eq!(None, file.offset(Position { line: 2, col: 10 }));
+        assert_eq!(None, file.offset(Position { line: 3, col: 1 }));
+
+        assert_eq!(Region::from((1, 2, 2)), file.region(Span::new(1, 3)));
+        assert_eq!(Region::from((2, 3, 8)), file.region(Span::new(6, 15)));
+
+        let mut locator = file.locator();
+        assert_eq!(Position { line: 2, col: 6 }, locator.position(12));
+        assert_eq!(Position { line: 2, col: 8 }, locator.position(14));
+        assert_eq!(Position { line: 1, col: 2 }, locator.position(1));
+        assert_eq!(Position { line: 2, col: 9 }, locator.position(15));
+    }
+}
@@ -1,16 +1,269 @@
+//! Bindings of the compiler for JavaScript, which the playground in
+//! `browser` uses.
+//!
+//! Every function throws its error as a `Diagnostic`, which is typed in
+//! the TypeScript definitions below, instead of returning it.
+
+use compiler::compile::{host_module, Options};
+use compiler::parse::lexer::lexer;
+use compiler::parse::token::Token as TokenKind;
+use compiler::reporting::{Region, SourceFile, Span};
+use serde::Serialize;
 use wasm_bindgen::prelude::wasm_bindgen;
-use wasm_bindgen::JsValue;
+use wasm_bindgen::{JsCast, JsValue};
+
+#[wasm_bindgen(typescript_custom_section)]
+const TYPES: &'static str = r#"
+/** Start line, start column, end line and end column, all 1-based and inclusive. */
+export type Region = [number, number, number, number];
+
+/** Byte offsets, where the end is exclusive. */
+export type Span = { start: number; end: number };
+
+/**
+ * The functions of the `io` namespace every compiled module imports,
+ * each returning its argument.
+ */
+export type Io = {
+  print: (value: number) => number;
+  println: (value: number) => number;
+};
+
+export type Module = { filename: string | null; expressions: Expr[] };
+
+export type Expr =
+  | { type: "Number"; region: Region; value: number }
+  | { type: "Boolean"; region: Region; value: boolean }
+  | { type: "Symbol"; region: Region; namespace: string[]; value: string }
+  | { type: "List"; region: Region; expressions: Expr[] }
+  | { type: "Vector"; region: Region; expressions: Expr[] };
+
+export type Token = {
+  token: "LParen" | "RParen" | "LBracket" | "RBracket" | { Symbol: string } | { Number: number } | { Boolean: boolean };
+  span: Span;
+  region: Region;
+};
+
+/** A problem in the source, which every function reports the same way. */
+export type Diagnostic = {
+  severity: "error" | "warning";
+  /** Identifies the kind of the diagnostic, like `W0011`, independent of its message. */
+  code: string;
+  message: string;
+  filename: string | null;
+  region: Region | null;
+  labels: { region: Region; message: string }[];
+  notes: string[];
+  suggestions: { message: string; region: Region; replacement: string }[];
+};
+"#;
+
+#[wasm_bindgen]
+extern "C" {
+    #[wasm_bindgen(typescript_type = "Module")]
+    pub type JsModule;
+
+    #[wasm_bindgen(typescript_type = "Token[]")]
+    pub type JsTokens;
+
+    #[wasm_bindgen(typescript_type = "Diagnostic[]")]
+    pub type JsDiagnostics;
+}
+
+#[derive(Serialize)]
+struct Token<'a> {
+    token: TokenKind<'a>,
+    span: Span,
+    region: Region,
+}
+
+/// Parses the source into a module.
+///
+/// @throws {Diagnostic}
+#[wasm_bindgen]
+pub fn parse(source: &str) -> Result<JsModule, JsValue> {
+    let module =
+        compiler::parse(None, source).map_err(|error| to_value(&error.to_diagnostic(None)))?;
+    Ok(to_value(&module).unchecked_into())
+}
 
+/// Compiles the source into a WebAssembly module, which imports `io`
+/// and exports its top-level expressions as `main`.
+///
+/// @throws {Diagnostic}
 #[wasm_bindgen]
-pub fn main(value: String) -> JsValue {
-    let result = compiler::parse(None, value.as_str());
-    let result = serde_wasm_bindgen::to_value(&result);
-    match result {
-        Ok(value) => value,
-        Err(error) => {
-            eprintln!("Error serializing result: {:?}", error);
-            serde_wasm_bindgen::to_value(&"An error.rs occurred during serialization")
-                .expect("Error handling failed")
+pub fn compile(source: &str) -> Result<Vec<u8>, JsValue> {
+    let output = compiler::compile::compile_source(None, source, &options())
+        .map_err(|error| to_value(&error.to_diagnostic()))?;
+    Ok(output.bytes)
+}
+
+/// Returns the tokens of the source.
+///
+/// @throws {Diagnostic}
+#[wasm_bindgen]
+pub fn tokens(source: &str) -> Result<JsTokens, JsValue> {
+    let tokens = lex(source).map_err(|error| to_value(&error.to_diagnostic(None)))?;
+    Ok(to_value(&tokens).unchecked_into())
+}
+
+fn lex(source: &str) -> Result<Vec<Token<'_>>, compiler::parse::error::Error> {
+    let file = SourceFile::new(None, source);
+    let mut locator = file.locator();
+    let mut tokens = vec![];
+    for lexeme in lexer(source) {
+        let lexeme = lexeme?;
+        tokens.push(Token {
+            token: lexeme.token,
+            span: lexeme.span,
+            region: locator.region(lexeme.span),
+        });
+    }
+    Ok(tokens)
+}
+
+/// Formats the source.
+///
+/// @throws {Diagnostic}
+#[wasm_bindgen]
+pub fn format(source: &str) -> Result<String, JsValue> {
+    compiler::parse::format::format(source).map_err(|error| to_value(&error.to_diagnostic(None)))
+}
+
+/// Returns the diagnostics of compiling the source, which are empty if
+/// it compiles.
+#[wasm_bindgen]
+pub fn diagnostics(source: &str) -> JsDiagnostics {
+    let errors: Vec<_> = compiler::compile::compile_source(None, source, &options())
+        .err()
+        .iter()
+        .map(|error| error.to_diagnostic())
+        .collect();
+    to_value(&errors).unchecked_into()
+}
+
+/// Returns the options every module is compiled with.
+fn options() -> Options {
+    Options {
+        imports: host_module("io").expect("io should be a host module"),
+        ..Options::default()
+    }
+}
+
+/// Converts the value into plain JavaScript objects and arrays, where
+/// `None` becomes `null`.
+fn to_value<T: Serialize + ?Sized>(value: &T) -> JsValue {
+    let serializer = serde_wasm_bindgen::Serializer::json_compatible();
+    value
+        .serialize(&serializer)
+        .expect("values of the compiler should serialize")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::lex;
+    use serde_json::Value;
+    use std::collections::BTreeSet;
+
+    /// Checks the values the functions return against the TypeScript
+    /// definitions, which are written by hand.
+    #[test]
+    fn values_match_typescript_definitions() {
+        // The custom section is not available as a constant.
+        let source = include_str!("lib.rs");
+        let start = source.find("const TYPES: &'static str = r#\"").unwrap();
+        let end = start + source[start..].find("\"#;").unwrap();
+        let types = &source[start..end];
+
+        let source = "(f 1 true [x] a/b)";
+        let module = compiler::parse(Some("main.edn".to_string()), source).unwrap();
+        let tokens = lex(source).unwrap();
+        let diagnostics: Vec<_> = [
+            "(defn double (x) (* 2 x))\n(doubel 1)",
+            "(defn f () 1)\n(defn f () 2)",
+        ]
+        .iter()
+        .map(|source| {
+            compiler::compile::compile(Some("main.edn".to_string()), source)
+                .unwrap_err()
+                .to_diagnostic()
+        })
+        .collect();
+        let values = [
+            serde_json::to_value(&module).unwrap(),
+            serde_json::to_value(&tokens).unwrap(),
+            serde_json::to_value(&diagnostics).unwrap(),
+        ];
+
+        let declared = object_types(types);
+        let mut objects = vec![];
+        for value in &values {
+            collect_objects(value, &mut objects);
+        }
+        for object in objects {
+            let keys: BTreeSet<String> = object.keys().cloned().collect();
+            assert!(
+                declared.contains(&keys),
+                "{:?} is not declared in {:?}",
+                keys,
+                declared
+            );
+            if let Some(Value::String(tag)) = object.get("type") {
+                assert!(types.contains(&format!("type: \"{}\"", tag)));
+            }
+            if let Some(Value::String(token)) = object.get("token") {
+                assert!(types.contains(&format!("\"{}\"", token)));
+            }
+        }
+    }
+
+    /// Returns the keys of every object type in the given definitions.
+    fn object_types(types: &str) -> Vec<BTreeSet<String>> {
+        let mut stack: Vec<BTreeSet<String>> = vec![];
+        let mut objects = vec![];
+        let mut word = String::new();
+        let mut rest = types;
+        while let Some(c) = rest.chars().next() {
+            if rest.starts_with("/*") {
+                let end = rest.find("*/").expect("comments should be closed");
+                rest = &rest[end + 2..];
+                continue;
+            }
+            rest = &rest[c.len_utf8()..];
+            match c {
+                '{' => stack.push(BTreeSet::new()),
+                '}' => objects.extend(stack.pop()),
+                ':' if !word.is_empty() => {
+                    if let Some(keys) = stack.last_mut() {
+                        keys.insert(word.clone());
+                    }
+                }
+                _ => {}
+            }
+            if c.is_alphanumeric() || c == '_' {
+                word.push(c);
+            } else {
+                word.clear();
+            }
+        }
+        objects
+    }
+
+    fn collect_objects<'a>(
+        value: &'a Value,
+        objects: &mut Vec<&'a serde_json::Map<String, Value>>,
+    ) {
+        match value {
+            Value::Object(object) => {
+                objects.push(object);
+                object
+                    .values()
+                    .for_each(|value| collect_objects(value, objects));
+            }
+            Value::Array(values) => values
+                .iter()
+                .for_each(|value| collect_objects(value, objects)),
+            _ => {}
         }
     }
 }